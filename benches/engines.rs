@@ -0,0 +1,102 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use logicproc::{
+    netlist::{Bit, Cell, Netlist, Port},
+    parallel::ParallelOptions,
+    util::XorShift,
+    yosys_parse::{CellType, Direction},
+    Circuit,
+};
+
+/// 幅width・深さdepthの段になった回路
+/// 各ゲートは一つ前の段から疑似乱数で選んだ2本を読む
+fn layered(width: usize, depth: usize) -> Netlist {
+    let types = [
+        CellType::And,
+        CellType::Or,
+        CellType::Xor,
+        CellType::Nand,
+        CellType::Nor,
+        CellType::Nxor,
+    ];
+    let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+    let mut next = || rng.next() as usize;
+    let wire = |level: usize, i: usize| (2 + level * width + i) as u32;
+    let mut cells = Vec::new();
+    for level in 1..=depth {
+        for i in 0..width {
+            cells.push(Cell {
+                name: format!("g{}_{}", level, i),
+                type_name: types[next() % types.len()],
+                inputs: vec![
+                    Bit::Wire(wire(level - 1, next() % width)),
+                    Bit::Wire(wire(level - 1, next() % width)),
+                ],
+                output: wire(level, i),
+            });
+        }
+    }
+    let port = |name: &str, direction, level| Port {
+        name: name.to_string(),
+        direction,
+        bits: (0..width).map(|i| Bit::Wire(wire(level, i))).collect(),
+    };
+    Netlist {
+        name: "layered".to_string(),
+        ports: vec![
+            port("A", Direction::In, 0),
+            port("Y", Direction::Out, depth),
+        ],
+        cells,
+        memories: Vec::new(),
+        netnames: Vec::new(),
+    }
+}
+
+fn circuit(netlist: &Netlist) -> Circuit<u64> {
+    let mut circuit = Circuit::<u64>::from_netlist(netlist.clone()).unwrap();
+    let width = netlist.port("A").unwrap().bits.len();
+    circuit
+        .set_port(
+            "A",
+            (0..width as u64)
+                .map(|i| i.wrapping_mul(0x5851_f42d))
+                .collect(),
+        )
+        .unwrap();
+    circuit
+}
+
+fn engines(c: &mut Criterion) {
+    let threads = ParallelOptions::default().threads;
+    let mut group = c.benchmark_group("evaluate");
+    group.sample_size(10);
+    for (width, depth) in [(256, 64), (4096, 32)] {
+        let netlist = layered(width, depth);
+        let size = format!("{}x{}", width, depth);
+
+        let mut single = circuit(&netlist);
+        group.bench_function(BenchmarkId::new("single", &size), |b| {
+            b.iter(|| single.evaluate().unwrap())
+        });
+        for chunk in [64, 256, 1024] {
+            let mut parallel = circuit(&netlist);
+            let options = ParallelOptions { threads, chunk };
+            group.bench_function(
+                BenchmarkId::new(format!("parallel{}/chunk{}", threads, chunk), &size),
+                |b| b.iter(|| parallel.evaluate_parallel(&options).unwrap()),
+            );
+        }
+        #[cfg(feature = "async")]
+        {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            let mut circuit = circuit(&netlist);
+            group.bench_function(BenchmarkId::new("async", &size), |b| {
+                b.iter(|| runtime.block_on(circuit.culc_async().unwrap()))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, engines);
+criterion_main!(benches);
@@ -0,0 +1,635 @@
+use crate::{
+    netlist::{Bit, Cell, Netlist, Port},
+    traits::{LogicConst, LogicOps},
+    yosys_parse::{CellType, Direction, WireId},
+};
+use std::collections::HashMap;
+
+/// # Sammary
+/// AIGの枝。nodeのindex*2 + 反転bit
+/// node 0は定数0なので、Lit(0)が0、Lit(1)が1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Lit(u32);
+impl Lit {
+    pub const FALSE: Lit = Lit(0);
+    pub const TRUE: Lit = Lit(1);
+    fn new(node: usize, neg: bool) -> Self {
+        Lit((node as u32) << 1 | neg as u32)
+    }
+    pub fn node(&self) -> usize {
+        (self.0 >> 1) as usize
+    }
+    pub fn is_neg(&self) -> bool {
+        self.0 & 1 == 1
+    }
+    pub fn is_const(&self) -> bool {
+        self.node() == 0
+    }
+}
+impl std::ops::Not for Lit {
+    type Output = Lit;
+    fn not(self) -> Lit {
+        Lit(self.0 ^ 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AigNode {
+    Const,
+    Input,
+    /// 入力は小さい順
+    And(Lit, Lit),
+}
+
+/// # Sammary
+/// 反転付きの枝を持つAnd-Inverter Graph
+/// nodeは入力側から順に並び、(入力, 入力)が同じANDは一つしか作らない
+/// # contents
+/// - inputs/outputs: ポート名とそのbitのリテラル(LSB first)
+#[derive(Debug, Clone)]
+pub struct Aig {
+    pub name: String,
+    nodes: Vec<AigNode>,
+    pub inputs: Vec<(String, Vec<Lit>)>,
+    pub outputs: Vec<(String, Vec<Lit>)>,
+    table: HashMap<(Lit, Lit), Lit>,
+}
+impl Default for Aig {
+    fn default() -> Self {
+        Aig {
+            name: String::new(),
+            nodes: vec![AigNode::Const],
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            table: HashMap::new(),
+        }
+    }
+}
+impl Aig {
+    pub fn nodes(&self) -> &[AigNode] {
+        &self.nodes
+    }
+    pub fn input(&mut self) -> Lit {
+        self.nodes.push(AigNode::Input);
+        Lit::new(self.nodes.len() - 1, false)
+    }
+    /// 定数・同じ入力・反転した入力は畳み込み、同じANDがあればそれを返す
+    pub fn and(&mut self, a: Lit, b: Lit) -> Lit {
+        let (a, b) = if a < b { (a, b) } else { (b, a) };
+        if a == Lit::FALSE || a == !b {
+            return Lit::FALSE;
+        }
+        if a == Lit::TRUE || a == b {
+            return b;
+        }
+        if let Some(&lit) = self.table.get(&(a, b)) {
+            return lit;
+        }
+        self.nodes.push(AigNode::And(a, b));
+        let lit = Lit::new(self.nodes.len() - 1, false);
+        self.table.insert((a, b), lit);
+        lit
+    }
+    pub fn or(&mut self, a: Lit, b: Lit) -> Lit {
+        !self.and(!a, !b)
+    }
+    pub fn xor(&mut self, a: Lit, b: Lit) -> Lit {
+        let x = self.and(a, !b);
+        let y = self.and(!a, b);
+        self.or(x, y)
+    }
+    fn fanins(&self, lit: Lit) -> Option<(Lit, Lit)> {
+        match self.nodes[lit.node()] {
+            AigNode::And(a, b) => Some((a, b)),
+            _ => None,
+        }
+    }
+    /// andに二段の規則を加えたもの
+    /// - (x & y) & x = x & y、(x & y) & !x = 0
+    /// - !(x & y) & !x = !x、!(x & y) & x = x & !y
+    /// - (x & y) & (z & w) で反転した入力を共有すれば0
+    fn and_rewrite(&mut self, a: Lit, b: Lit) -> Lit {
+        for (a, b) in [(a, b), (b, a)] {
+            if let Some((x, y)) = self.fanins(a) {
+                if a.is_neg() {
+                    if b == !x || b == !y {
+                        return b;
+                    }
+                    if b == x {
+                        return self.and(x, !y);
+                    }
+                    if b == y {
+                        return self.and(y, !x);
+                    }
+                } else {
+                    if b == x || b == y {
+                        return a;
+                    }
+                    if b == !x || b == !y {
+                        return Lit::FALSE;
+                    }
+                    if let Some((z, w)) = self.fanins(b).filter(|_| !b.is_neg()) {
+                        if [z, w].iter().any(|&l| l == !x || l == !y) {
+                            return Lit::FALSE;
+                        }
+                    }
+                }
+            }
+        }
+        self.and(a, b)
+    }
+    pub fn and_count(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|n| matches!(n, AigNode::And(..)))
+            .count()
+    }
+    fn levels(&self) -> Vec<usize> {
+        let mut levels = vec![0; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            if let AigNode::And(a, b) = node {
+                levels[i] = levels[a.node()].max(levels[b.node()]) + 1;
+            }
+        }
+        levels
+    }
+    /// 出力までの最大のAND段数
+    pub fn depth(&self) -> usize {
+        let levels = self.levels();
+        self.output_lits()
+            .map(|l| levels[l.node()])
+            .max()
+            .unwrap_or(0)
+    }
+    fn output_lits(&self) -> impl Iterator<Item = Lit> + '_ {
+        self.outputs
+            .iter()
+            .flat_map(|(_, lits)| lits.iter().copied())
+    }
+    /// 出力から届くnode
+    fn reachable(&self) -> Vec<bool> {
+        let mut live = vec![false; self.nodes.len()];
+        for lit in self.output_lits() {
+            live[lit.node()] = true;
+        }
+        for i in (0..self.nodes.len()).rev() {
+            if let (true, AigNode::And(a, b)) = (live[i], self.nodes[i]) {
+                live[a.node()] = true;
+                live[b.node()] = true;
+            }
+        }
+        live
+    }
+
+    /// # Sammary
+    /// メモリのないnetlistからAIGを作る
+    /// XORは3つのANDに展開する
+    pub fn from_netlist(netlist: &Netlist) -> Result<Self, &'static str> {
+        if !netlist.memories.is_empty() {
+            return Err("memory is not supported");
+        }
+        let order = netlist.topo_order()?;
+        let mut aig = Aig {
+            name: netlist.name.clone(),
+            ..Default::default()
+        };
+        let mut lits: HashMap<WireId, Lit> = HashMap::new();
+        for port in netlist.input_ports() {
+            let mut bits = Vec::new();
+            for bit in port.bits.iter() {
+                bits.push(match bit {
+                    Bit::Wire(id) => *lits.entry(*id).or_insert_with(|| aig.input()),
+                    Bit::Const(c) => Lit::new(0, *c),
+                });
+            }
+            aig.inputs.push((port.name.clone(), bits));
+        }
+        let lit = |lits: &HashMap<WireId, Lit>, bit: &Bit| -> Result<Lit, &'static str> {
+            match bit {
+                Bit::Wire(id) => lits.get(id).copied().ok_or("undriven wire"),
+                Bit::Const(c) => Ok(Lit::new(0, *c)),
+            }
+        };
+        for &i in order.iter() {
+            let cell = &netlist.cells[i];
+            let a = lit(&lits, &cell.inputs[0])?;
+            let b = match cell.inputs.get(1) {
+                Some(bit) => lit(&lits, bit)?,
+                None => a,
+            };
+            let out = match cell.type_name {
+                CellType::And => aig.and(a, b),
+                CellType::Nand => !aig.and(a, b),
+                CellType::Or => aig.or(a, b),
+                CellType::Nor => !aig.or(a, b),
+                CellType::Xor => aig.xor(a, b),
+                CellType::Nxor => !aig.xor(a, b),
+                CellType::Not => !a,
+                CellType::Mem | CellType::MemV2 => return Err("memory is not supported"),
+            };
+            lits.insert(cell.output, out);
+        }
+        for port in netlist.output_ports() {
+            let bits = port
+                .bits
+                .iter()
+                .map(|b| lit(&lits, b))
+                .collect::<Result<_, _>>()?;
+            aig.outputs.push((port.name.clone(), bits));
+        }
+        Ok(aig)
+    }
+
+    /// # Sammary
+    /// ANDは$_AND_、反転した枝は$_NOT_のcellにしたnetlist
+    /// 出力から届かないnodeは書き出さない
+    pub fn to_netlist(&self) -> Netlist {
+        let live = self.reachable();
+        let mut writer = Writer {
+            next: 1,
+            wires: vec![None; self.nodes.len()],
+            nots: HashMap::new(),
+            cells: Vec::new(),
+        };
+        let mut ports = Vec::new();
+        for (name, lits) in self.inputs.iter() {
+            for lit in lits.iter().filter(|l| !l.is_const()) {
+                if writer.wires[lit.node()].is_none() {
+                    writer.wires[lit.node()] = Some(writer.wire());
+                }
+            }
+            ports.push(Port {
+                name: name.clone(),
+                direction: Direction::In,
+                bits: lits.iter().map(|&l| writer.bit(l)).collect(),
+            });
+        }
+        for (i, node) in self.nodes.iter().enumerate() {
+            if let (true, AigNode::And(a, b)) = (live[i], node) {
+                let inputs = vec![writer.bit(*a), writer.bit(*b)];
+                let output = writer.wire();
+                writer.wires[i] = Some(output);
+                writer.cells.push(Cell {
+                    name: format!("and{}", i),
+                    type_name: CellType::And,
+                    inputs,
+                    output,
+                });
+            }
+        }
+        for (name, lits) in self.outputs.iter() {
+            ports.push(Port {
+                name: name.clone(),
+                direction: Direction::Out,
+                bits: lits.iter().map(|&l| writer.bit(l)).collect(),
+            });
+        }
+        ports.sort_by(|a, b| a.name.cmp(&b.name));
+        let netnames = ports
+            .iter()
+            .map(|p| (p.name.clone(), p.bits.clone()))
+            .collect();
+        Netlist {
+            name: self.name.clone(),
+            ports,
+            cells: writer.cells,
+            memories: Vec::new(),
+            netnames,
+        }
+    }
+
+    /// # Sammary
+    /// 入力ポートの値から出力ポートの値を求める。値はLSB first
+    pub fn eval<T>(
+        &self,
+        inputs: &[(String, Vec<T>)],
+    ) -> Result<Vec<(String, Vec<T>)>, &'static str>
+    where
+        T: LogicOps + LogicConst + Clone,
+    {
+        let mut values: Vec<Option<T>> = vec![None; self.nodes.len()];
+        values[0] = Some(T::lgc_const(false));
+        for (name, lits) in self.inputs.iter() {
+            let (_, vals) = inputs
+                .iter()
+                .find(|(n, _)| n == name)
+                .ok_or("input port is not given")?;
+            if vals.len() != lits.len() {
+                return Err("width mismatch");
+            }
+            for (lit, val) in lits.iter().zip(vals.iter()) {
+                if !lit.is_const() {
+                    values[lit.node()] = Some(if lit.is_neg() {
+                        val.lgc_not()
+                    } else {
+                        val.clone()
+                    });
+                }
+            }
+        }
+        let value = |values: &[Option<T>], lit: Lit| -> Result<T, &'static str> {
+            let val = values[lit.node()].as_ref().ok_or("input is not given")?;
+            Ok(if lit.is_neg() {
+                val.lgc_not()
+            } else {
+                val.clone()
+            })
+        };
+        for i in 0..self.nodes.len() {
+            if let AigNode::And(a, b) = self.nodes[i] {
+                values[i] = Some(value(&values, a)?.lgc_and(&value(&values, b)?));
+            }
+        }
+        self.outputs
+            .iter()
+            .map(|(name, lits)| {
+                let vals = lits
+                    .iter()
+                    .map(|&l| value(&values, l))
+                    .collect::<Result<_, _>>()?;
+                Ok((name.clone(), vals))
+            })
+            .collect()
+    }
+
+    /// 入力とポートだけを写した空のAIGと、旧node -> 新リテラルの表
+    fn rebuild_base(&self) -> (Aig, Vec<Option<Lit>>) {
+        let mut aig = Aig {
+            name: self.name.clone(),
+            ..Default::default()
+        };
+        let mut map: Vec<Option<Lit>> = vec![None; self.nodes.len()];
+        map[0] = Some(Lit::FALSE);
+        for (i, node) in self.nodes.iter().enumerate() {
+            if *node == AigNode::Input {
+                map[i] = Some(aig.input());
+            }
+        }
+        aig.inputs = self.inputs.clone();
+        for (_, lits) in aig.inputs.iter_mut() {
+            for l in lits.iter_mut() {
+                *l = translate(&map, *l);
+            }
+        }
+        (aig, map)
+    }
+    fn finish(mut self, old: &Aig, map: &[Option<Lit>]) -> Aig {
+        self.outputs = old
+            .outputs
+            .iter()
+            .map(|(n, lits)| (n.clone(), lits.iter().map(|&l| translate(map, l)).collect()))
+            .collect();
+        self
+    }
+
+    /// # Sammary
+    /// 出力から届くnodeを二段の局所的な規則で作り直す
+    /// nodeが減らなくなるまで繰り返す
+    pub fn rewrite(&self) -> Aig {
+        let mut best = self.compact();
+        loop {
+            let live = best.reachable();
+            let (mut aig, mut map) = best.rebuild_base();
+            for (i, node) in best.nodes.iter().enumerate() {
+                if let (true, AigNode::And(a, b)) = (live[i], *node) {
+                    let (a, b) = (translate(&map, a), translate(&map, b));
+                    map[i] = Some(aig.and_rewrite(a, b));
+                }
+            }
+            // 規則で作ったANDの代わりに使われなくなったnodeを落とす
+            let next = aig.finish(&best, &map).compact();
+            if next.and_count() >= best.and_count() {
+                return best;
+            }
+            best = next;
+        }
+    }
+
+    /// 出力から届くnodeだけを残す
+    pub fn compact(&self) -> Aig {
+        let live = self.reachable();
+        let (mut aig, mut map) = self.rebuild_base();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if let (true, AigNode::And(a, b)) = (live[i], *node) {
+                map[i] = Some(aig.and(translate(&map, a), translate(&map, b)));
+            }
+        }
+        aig.finish(self, &map)
+    }
+
+    /// # Sammary
+    /// 反転していない枝でつながったファンアウト1のANDの木を多入力のANDとみなし、
+    /// 段数の浅いものから二つずつ組み直して深さを減らす
+    pub fn balance(&self) -> Aig {
+        let live = self.reachable();
+        let mut fanout = vec![0usize; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            if let (true, AigNode::And(a, b)) = (live[i], node) {
+                fanout[a.node()] += 1;
+                fanout[b.node()] += 1;
+            }
+        }
+        for lit in self.output_lits() {
+            fanout[lit.node()] += 1;
+        }
+        // 木の根と、その葉
+        let mut needed = vec![false; self.nodes.len()];
+        for lit in self.output_lits() {
+            needed[lit.node()] = true;
+        }
+        let mut leaves: Vec<Vec<Lit>> = vec![Vec::new(); self.nodes.len()];
+        for i in (0..self.nodes.len()).rev() {
+            if !needed[i] || !matches!(self.nodes[i], AigNode::And(..)) {
+                continue;
+            }
+            let mut stack = vec![Lit::new(i, false)];
+            while let Some(lit) = stack.pop() {
+                match self.fanins(lit) {
+                    Some((a, b))
+                        if lit.node() == i || (!lit.is_neg() && fanout[lit.node()] == 1) =>
+                    {
+                        stack.push(a);
+                        stack.push(b);
+                    }
+                    _ => {
+                        needed[lit.node()] = true;
+                        leaves[i].push(lit);
+                    }
+                }
+            }
+        }
+
+        let (mut aig, mut map) = self.rebuild_base();
+        let mut levels: Vec<usize> = vec![0; aig.nodes.len()];
+        for i in 0..self.nodes.len() {
+            if !needed[i] || !matches!(self.nodes[i], AigNode::And(..)) {
+                continue;
+            }
+            let mut lits: Vec<Lit> = leaves[i].iter().map(|&l| translate(&map, l)).collect();
+            // 段数の深い順に並べ、末尾の浅い二つを組む
+            let level = |levels: &[usize], l: &Lit| levels[l.node()];
+            lits.sort_by_key(|l| std::cmp::Reverse(level(&levels, l)));
+            while lits.len() > 1 {
+                let (a, b) = (lits.pop().unwrap(), lits.pop().unwrap());
+                let lit = aig.and(a, b);
+                if levels.len() < aig.nodes.len() {
+                    levels.push(level(&levels, &a).max(level(&levels, &b)) + 1);
+                }
+                let pos = lits
+                    .iter()
+                    .position(|l| level(&levels, l) <= level(&levels, &lit))
+                    .unwrap_or(lits.len());
+                lits.insert(pos, lit);
+            }
+            map[i] = Some(lits.pop().unwrap_or(Lit::TRUE));
+        }
+        aig.finish(self, &map)
+    }
+}
+
+/// to_netlistでwireidを振りながらcellを並べる
+struct Writer {
+    next: WireId,
+    /// nodeの出力のwireid
+    wires: Vec<Option<WireId>>,
+    /// node -> その反転のwireid
+    nots: HashMap<usize, WireId>,
+    cells: Vec<Cell>,
+}
+impl Writer {
+    fn wire(&mut self) -> WireId {
+        self.next += 1;
+        self.next
+    }
+    /// 反転した枝には$_NOT_を一つだけ作る
+    fn bit(&mut self, lit: Lit) -> Bit {
+        if lit.is_const() {
+            return Bit::Const(lit.is_neg());
+        }
+        let wire = self.wires[lit.node()].expect("node is not written");
+        if !lit.is_neg() {
+            return Bit::Wire(wire);
+        }
+        if let Some(&out) = self.nots.get(&lit.node()) {
+            return Bit::Wire(out);
+        }
+        let out = self.wire();
+        self.nots.insert(lit.node(), out);
+        self.cells.push(Cell {
+            name: format!("not{}", lit.node()),
+            type_name: CellType::Not,
+            inputs: vec![Bit::Wire(wire)],
+            output: out,
+        });
+        Bit::Wire(out)
+    }
+}
+
+/// 旧リテラルを新しいAIGのリテラルに写す
+fn translate(map: &[Option<Lit>], lit: Lit) -> Lit {
+    let new = map[lit.node()].expect("node is not translated");
+    if lit.is_neg() {
+        !new
+    } else {
+        new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        netlist::{Cell, Port},
+        util::XorShift,
+        yosys_parse::Direction,
+        Circuit,
+    };
+
+    /// 乱数の64パターンでAIGと元の回路の出力を比べる
+    fn check(netlist: &Netlist, aig: &Aig) {
+        let mut circuit = Circuit::<u64>::from_netlist(netlist.clone()).unwrap();
+        let mut rng = XorShift(7);
+        let inputs: Vec<(String, Vec<u64>)> = netlist
+            .input_ports()
+            .map(|p| (p.name.clone(), p.bits.iter().map(|_| rng.next()).collect()))
+            .collect();
+        for (name, vals) in inputs.iter() {
+            circuit.set_port(name, vals.clone()).unwrap();
+        }
+        circuit.evaluate().unwrap();
+        for (name, vals) in aig.eval(&inputs).unwrap() {
+            assert_eq!(circuit.get_port(&name).unwrap(), vals, "{}", name);
+        }
+    }
+
+    #[test]
+    fn ripple_aig() {
+        let netlist = Netlist::from_yosys(include_str!("../yosys/sample/ripple_res.json")).unwrap();
+        let aig = Aig::from_netlist(&netlist).unwrap();
+        check(&netlist, &aig);
+
+        let rewritten = aig.rewrite();
+        assert!(rewritten.and_count() <= aig.and_count());
+        check(&netlist, &rewritten);
+        let balanced = rewritten.balance();
+        assert!(balanced.depth() <= rewritten.depth());
+        check(&netlist, &balanced);
+
+        // netlistに戻しても同じ回路
+        let back = balanced.to_netlist();
+        assert_eq!(back.to_yosys().map(|_| ()), Ok(()));
+        check(&back, &balanced);
+    }
+
+    #[test]
+    fn rewrite_netlist() {
+        // y = (a0 & b0) & a0 は二段の規則で a0 & b0 になる
+        let mut netlist =
+            Netlist::from_yosys(include_str!("../yosys/sample/ripple_res.json")).unwrap();
+        let a0 = netlist.port("A").unwrap().bits[0];
+        let b0 = netlist.port("B").unwrap().bits[0];
+        let next = netlist.max_wireid();
+        for (i, inputs) in [vec![a0, b0], vec![Bit::Wire(next + 1), a0]]
+            .into_iter()
+            .enumerate()
+        {
+            netlist.cells.push(Cell {
+                name: format!("absorb{}", i),
+                type_name: CellType::And,
+                inputs,
+                output: next + 1 + i as WireId,
+            });
+        }
+        netlist.ports.push(Port {
+            name: "Y".to_string(),
+            direction: Direction::Out,
+            bits: vec![Bit::Wire(next + 2)],
+        });
+        let aig = Aig::from_netlist(&netlist).unwrap();
+        let rewritten = aig.rewrite();
+        assert!(rewritten.and_count() < aig.compact().and_count());
+        check(&netlist, &rewritten);
+    }
+
+    #[test]
+    fn rules() {
+        let mut aig = Aig::default();
+        let (x, y) = (aig.input(), aig.input());
+        let xy = aig.and(x, y);
+        assert_eq!(aig.and(y, x), xy);
+        assert_eq!(aig.and(x, !x), Lit::FALSE);
+        assert_eq!(aig.and_rewrite(xy, x), xy);
+        assert_eq!(aig.and_rewrite(!xy, !x), !x);
+        assert_eq!(aig.and_rewrite(xy, !y), Lit::FALSE);
+
+        // 8入力のANDの鎖は深さ3になる
+        let mut chain = Aig::default();
+        let ins: Vec<Lit> = (0..8).map(|_| chain.input()).collect();
+        let out = ins[1..].iter().fold(ins[0], |acc, &l| chain.and(acc, l));
+        chain.inputs.push(("a".to_string(), ins));
+        chain.outputs.push(("y".to_string(), vec![out]));
+        assert_eq!(chain.depth(), 7);
+        let balanced = chain.balance();
+        assert_eq!((balanced.depth(), balanced.and_count()), (3, 7));
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{
+    cnf::Cnf,
+    equiv::{miter, MITER_OUTPUT},
+    fault::{fault_simulate, Fault},
+    netlist::Netlist,
+    sat::{port_assignment, SatResult, Solver},
+    util::XorShift,
+    vectors::VectorSet,
+};
+
+pub struct AtpgOptions {
+    /// 最初に試すランダムなベクタの本数
+    pub random_patterns: usize,
+    pub seed: u64,
+    /// 故障1つあたりのSATソルバの衝突回数の上限。Noneなら無制限
+    pub conflict_limit: Option<u64>,
+}
+impl Default for AtpgOptions {
+    fn default() -> Self {
+        AtpgOptions {
+            random_patterns: 64,
+            seed: 0x9e37_79b9_7f4a_7c15,
+            conflict_limit: Some(100_000),
+        }
+    }
+}
+
+/// # contents
+/// - vectors: 生成したベクタ。detectedをすべて検出する
+/// - redundant: 検出できないことが証明された故障
+/// - aborted: 衝突回数の上限で諦めた故障
+#[derive(Debug, Clone)]
+pub struct AtpgResult {
+    pub vectors: VectorSet,
+    pub detected: Vec<Fault>,
+    pub redundant: Vec<Fault>,
+    pub aborted: Vec<Fault>,
+}
+
+/// # Sammary
+/// SATベースのATPG
+/// ランダムなベクタで簡単な故障を落とした後、残りの故障毎に
+/// 正常回路と故障回路のミターをSATで解いてベクタを作る
+/// 新しいベクタは故障シミュレーションにかけて、ついでに検出できる故障を落とす
+/// 最後に逆順の故障シミュレーションで不要なベクタを削る
+pub fn atpg(
+    netlist: &Netlist,
+    faults: &[Fault],
+    options: &AtpgOptions,
+) -> Result<AtpgResult, &'static str> {
+    let mut vectors = VectorSet::for_inputs(netlist);
+    let mut remaining: Vec<Fault> = faults.to_vec();
+    let mut redundant = Vec::new();
+    let mut aborted = Vec::new();
+
+    let mut rng = XorShift(options.seed | 1);
+    let mut random = VectorSet::for_inputs(netlist);
+    for _ in 0..options.random_patterns {
+        random.push(
+            netlist
+                .input_ports()
+                .map(|p| p.bits.iter().map(|_| rng.next() & 1 == 1).collect())
+                .collect(),
+        );
+    }
+    // ランダムなベクタは新しく故障を検出したものだけ残す
+    for i in 0..random.len() {
+        let mut single = VectorSet::new(random.ports.clone());
+        single.push(random.patterns[i].clone());
+        let report = fault_simulate(netlist, &single, &remaining)?;
+        if !report.detected.is_empty() {
+            vectors.push(single.patterns.remove(0));
+            remaining = report.undetected;
+        }
+    }
+
+    while let Some(fault) = remaining.pop() {
+        let m = miter(netlist, &fault.inject(netlist))?;
+        let mut cnf = Cnf::from_netlist(&m)?;
+        cnf.constrain_port(MITER_OUTPUT, &[Some(true)])?;
+        let mut solver = Solver::from_cnf(&cnf);
+        solver.conflict_limit = options.conflict_limit;
+        match solver.solve() {
+            SatResult::Sat(model) => {
+                let assign = port_assignment(&m, &cnf, &model);
+                let mut single = VectorSet::new(vectors.ports.clone());
+                single.push(
+                    single
+                        .ports
+                        .iter()
+                        .map(|name| {
+                            assign
+                                .iter()
+                                .find(|(n, _)| n == name)
+                                .map(|(_, v)| v.clone())
+                                .unwrap_or_default()
+                        })
+                        .collect(),
+                );
+                remaining = fault_simulate(netlist, &single, &remaining)?.undetected;
+                vectors.push(single.patterns.remove(0));
+            }
+            SatResult::Unsat => redundant.push(fault),
+            SatResult::Unknown => aborted.push(fault),
+        }
+    }
+
+    // 後ろのベクタから故障シミュレーションして、新しく検出しないベクタを捨てる
+    let mut targets: Vec<Fault> = faults
+        .iter()
+        .filter(|f| !redundant.contains(f) && !aborted.contains(f))
+        .copied()
+        .collect();
+    let mut compact = VectorSet::new(vectors.ports.clone());
+    let mut detected = Vec::new();
+    for pattern in vectors.patterns.into_iter().rev() {
+        let mut single = VectorSet::new(compact.ports.clone());
+        single.push(pattern);
+        let report = fault_simulate(netlist, &single, &targets)?;
+        if !report.detected.is_empty() {
+            compact.push(single.patterns.remove(0));
+            detected.extend(report.detected);
+            targets = report.undetected;
+        }
+    }
+    compact.patterns.reverse();
+
+    Ok(AtpgResult {
+        vectors: compact,
+        detected,
+        redundant,
+        aborted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fault::enumerate_faults,
+        netlist::{Bit, Cell},
+        testutil::{hazard_sample, set_port_bits},
+        yosys_parse::CellType,
+    };
+
+    #[test]
+    fn full_adder() {
+        let netlist = Netlist::from_yosys(include_str!("yosys_sample.v")).unwrap();
+        let faults = enumerate_faults(&netlist);
+        let res = atpg(&netlist, &faults, &AtpgOptions::default()).unwrap();
+        assert_eq!(res.detected.len(), faults.len());
+        assert!(res.vectors.len() < 256);
+
+        // 書き出したベクタを読み直して故障シミュレーションで確かめる
+        let replay = VectorSet::parse(&res.vectors.to_string()).unwrap();
+        let report = fault_simulate(&netlist, &replay, &faults).unwrap();
+        assert_eq!(report.coverage(), 100.0);
+    }
+
+    #[test]
+    fn redundant() {
+        // carry | (A[0] & !A[0]) の冗長なANDを足す
+        let (mut netlist, y) = hazard_sample();
+        let carry = netlist.port("carry").unwrap().bits[0];
+        netlist.cells.push(Cell {
+            name: "redundant".to_string(),
+            type_name: CellType::Or,
+            inputs: vec![carry, Bit::Wire(y)],
+            output: y + 1,
+        });
+        set_port_bits(&mut netlist, "carry", vec![Bit::Wire(y + 1)]);
+
+        let faults = enumerate_faults(&netlist);
+        let options = AtpgOptions {
+            random_patterns: 0,
+            ..Default::default()
+        };
+        let res = atpg(&netlist, &faults, &options).unwrap();
+        assert!(res.aborted.is_empty());
+        assert!(!res.redundant.is_empty());
+        assert_eq!(res.detected.len() + res.redundant.len(), faults.len());
+        let report = fault_simulate(&netlist, &res.vectors, &faults).unwrap();
+        assert_eq!(report.detected.len(), res.detected.len());
+    }
+}
@@ -0,0 +1,560 @@
+use crate::{
+    traits::{LogicConst, LogicOps},
+    yosys_parse::WireId,
+    Circuit,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+pub type BddRef = Arc<Mutex<BddManager>>;
+
+const FALSE: u32 = 0;
+const TRUE: u32 = 1;
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+enum Op {
+    And,
+    Or,
+    Xor,
+}
+
+/// # Sammary
+/// ROBDDの管理
+/// # contents
+/// - nodes: (var, lo, hi)。0,1は終端
+/// - unique: 同じ(var, lo, hi)のnodeを一つにする
+/// - computed: apply結果のキャッシュ
+/// - order: level -> var。level[var]はその逆
+pub struct BddManager {
+    nodes: Vec<(u32, u32, u32)>,
+    unique: HashMap<(u32, u32, u32), u32>,
+    computed: HashMap<(Op, u32, u32), u32>,
+    order: Vec<u32>,
+    level: Vec<u32>,
+}
+impl BddManager {
+    pub fn new() -> Self {
+        BddManager {
+            nodes: vec![(u32::MAX, FALSE, FALSE), (u32::MAX, TRUE, TRUE)],
+            unique: HashMap::new(),
+            computed: HashMap::new(),
+            order: Vec::new(),
+            level: Vec::new(),
+        }
+    }
+    /// order[i]番目のlevelにvar order[i]を置く
+    pub fn with_order(order: &[usize]) -> Option<Self> {
+        let mut mgr = Self::new();
+        let mut level = vec![u32::MAX; order.len()];
+        for (l, &v) in order.iter().enumerate() {
+            if *level.get(v)? != u32::MAX {
+                return None;
+            }
+            level[v] = l as u32;
+        }
+        mgr.order = order.iter().map(|&v| v as u32).collect();
+        mgr.level = level;
+        Some(mgr)
+    }
+    pub fn into_ref(self) -> BddRef {
+        Arc::new(Mutex::new(self))
+    }
+    pub fn var_count(&self) -> usize {
+        self.order.len()
+    }
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+    /// 最下位levelに変数を追加する
+    pub fn new_var(&mut self) -> usize {
+        let v = self.order.len();
+        self.order.push(v as u32);
+        self.level.push(v as u32);
+        v
+    }
+    /// まだ無い変数は最下位levelに足してから作る
+    pub fn var(&mut self, v: usize) -> u32 {
+        while self.level.len() <= v {
+            self.new_var();
+        }
+        self.mk(v as u32, FALSE, TRUE)
+    }
+    fn level_of(&self, f: u32) -> u32 {
+        match f {
+            FALSE | TRUE => self.order.len() as u32,
+            _ => self.level[self.nodes[f as usize].0 as usize],
+        }
+    }
+    fn mk(&mut self, var: u32, lo: u32, hi: u32) -> u32 {
+        if lo == hi {
+            return lo;
+        }
+        if let Some(&id) = self.unique.get(&(var, lo, hi)) {
+            return id;
+        }
+        let id = self.nodes.len() as u32;
+        self.nodes.push((var, lo, hi));
+        self.unique.insert((var, lo, hi), id);
+        id
+    }
+    /// levelの変数でのcofactor
+    fn cofactor(&self, f: u32, level: u32) -> (u32, u32) {
+        if self.level_of(f) == level {
+            let (_, lo, hi) = self.nodes[f as usize];
+            (lo, hi)
+        } else {
+            (f, f)
+        }
+    }
+    fn apply(&mut self, op: Op, f: u32, g: u32) -> u32 {
+        match op {
+            Op::And => {
+                if f == FALSE || g == FALSE {
+                    return FALSE;
+                }
+                if f == TRUE || f == g {
+                    return g;
+                }
+                if g == TRUE {
+                    return f;
+                }
+            }
+            Op::Or => {
+                if f == TRUE || g == TRUE {
+                    return TRUE;
+                }
+                if f == FALSE || f == g {
+                    return g;
+                }
+                if g == FALSE {
+                    return f;
+                }
+            }
+            Op::Xor => {
+                if f == g {
+                    return FALSE;
+                }
+                if f == FALSE {
+                    return g;
+                }
+                if g == FALSE {
+                    return f;
+                }
+            }
+        }
+        let key = (op, f.min(g), f.max(g));
+        if let Some(&r) = self.computed.get(&key) {
+            return r;
+        }
+        let level = self.level_of(f).min(self.level_of(g));
+        let (f0, f1) = self.cofactor(f, level);
+        let (g0, g1) = self.cofactor(g, level);
+        let lo = self.apply(op, f0, g0);
+        let hi = self.apply(op, f1, g1);
+        let r = self.mk(self.order[level as usize], lo, hi);
+        self.computed.insert(key, r);
+        r
+    }
+    pub fn and(&mut self, f: u32, g: u32) -> u32 {
+        self.apply(Op::And, f, g)
+    }
+    pub fn or(&mut self, f: u32, g: u32) -> u32 {
+        self.apply(Op::Or, f, g)
+    }
+    pub fn xor(&mut self, f: u32, g: u32) -> u32 {
+        self.apply(Op::Xor, f, g)
+    }
+    pub fn not(&mut self, f: u32) -> u32 {
+        self.apply(Op::Xor, f, TRUE)
+    }
+    /// 変数vをvalに固定したもの。managerに無い変数ならfはvに依存しないのでそのまま
+    pub fn restrict(&mut self, f: u32, v: usize, val: bool) -> u32 {
+        let Some(&level) = self.level.get(v) else {
+            return f;
+        };
+        let mut memo = HashMap::new();
+        self.restrict_(f, level, val, &mut memo)
+    }
+    fn restrict_(&mut self, f: u32, level: u32, val: bool, memo: &mut HashMap<u32, u32>) -> u32 {
+        let l = self.level_of(f);
+        if l > level {
+            return f;
+        }
+        let (var, lo, hi) = self.nodes[f as usize];
+        if l == level {
+            return if val { hi } else { lo };
+        }
+        if let Some(&r) = memo.get(&f) {
+            return r;
+        }
+        let lo = self.restrict_(lo, level, val, memo);
+        let hi = self.restrict_(hi, level, val, memo);
+        let r = self.mk(var, lo, hi);
+        memo.insert(f, r);
+        r
+    }
+    /// 変数vの存在限量
+    pub fn exists(&mut self, f: u32, v: usize) -> u32 {
+        let f0 = self.restrict(f, v, false);
+        let f1 = self.restrict(f, v, true);
+        self.or(f0, f1)
+    }
+    /// 変数vの全称限量
+    pub fn forall(&mut self, f: u32, v: usize) -> u32 {
+        let f0 = self.restrict(f, v, false);
+        let f1 = self.restrict(f, v, true);
+        self.and(f0, f1)
+    }
+    /// 全変数(var_count個)の割当のうちfを真にするものの数
+    /// u128に収まらなければNone
+    pub fn sat_count(&self, f: u32) -> Option<u128> {
+        let mut memo = HashMap::new();
+        let c = self.sat_count_(f, &mut memo)?;
+        shl(c, self.level_of(f))
+    }
+    fn sat_count_(&self, f: u32, memo: &mut HashMap<u32, u128>) -> Option<u128> {
+        match f {
+            FALSE => return Some(0),
+            TRUE => return Some(1),
+            _ => {}
+        }
+        if let Some(&c) = memo.get(&f) {
+            return Some(c);
+        }
+        let (_, lo, hi) = self.nodes[f as usize];
+        let l = self.level_of(f);
+        let c0 = shl(self.sat_count_(lo, memo)?, self.level_of(lo) - l - 1)?;
+        let c1 = shl(self.sat_count_(hi, memo)?, self.level_of(hi) - l - 1)?;
+        let c = c0.checked_add(c1)?;
+        memo.insert(f, c);
+        Some(c)
+    }
+    /// fを真にする割当を一つ返す。Noneの変数はどちらでもよい
+    pub fn any_sat(&self, f: u32) -> Option<Vec<Option<bool>>> {
+        if f == FALSE {
+            return None;
+        }
+        let mut res = vec![None; self.var_count()];
+        let mut f = f;
+        while f != TRUE {
+            let (var, lo, hi) = self.nodes[f as usize];
+            if lo != FALSE {
+                res[var as usize] = Some(false);
+                f = lo;
+            } else {
+                res[var as usize] = Some(true);
+                f = hi;
+            }
+        }
+        Some(res)
+    }
+    pub fn eval(&self, f: u32, assign: &[bool]) -> bool {
+        let mut f = f;
+        while f != TRUE && f != FALSE {
+            let (var, lo, hi) = self.nodes[f as usize];
+            f = if assign[var as usize] { hi } else { lo };
+        }
+        f == TRUE
+    }
+    /// fが依存する変数(昇順)
+    pub fn support(&self, f: u32) -> Vec<usize> {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut vars = vec![false; self.var_count()];
+        let mut stack = vec![f];
+        while let Some(f) = stack.pop() {
+            if f == TRUE || f == FALSE || seen[f as usize] {
+                continue;
+            }
+            seen[f as usize] = true;
+            let (var, lo, hi) = self.nodes[f as usize];
+            vars[var as usize] = true;
+            stack.push(lo);
+            stack.push(hi);
+        }
+        (0..vars.len()).filter(|&v| vars[v]).collect()
+    }
+    /// fを根とするnodeの数(終端を除く)
+    pub fn size(&self, f: u32) -> usize {
+        let mut seen = std::collections::HashSet::new();
+        let mut stack = vec![f];
+        while let Some(f) = stack.pop() {
+            if f == TRUE || f == FALSE || !seen.insert(f) {
+                continue;
+            }
+            let (_, lo, hi) = self.nodes[f as usize];
+            stack.push(lo);
+            stack.push(hi);
+        }
+        seen.len()
+    }
+}
+impl Default for BddManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 桁あふれするとNoneになるc << s
+fn shl(c: u128, s: u32) -> Option<u128> {
+    if c == 0 {
+        Some(0)
+    } else if s <= c.leading_zeros() {
+        Some(c << s)
+    } else {
+        None
+    }
+}
+
+/// # Sammary
+/// BddManagerのnodeへのハンドル
+/// 定数はどのmanagerでも0,1なのでmanagerを持たなくてよい
+#[derive(Clone)]
+pub struct Bdd {
+    mgr: Option<BddRef>,
+    id: u32,
+}
+impl Bdd {
+    pub fn new_var(mgr: &BddRef) -> (Self, usize) {
+        let mut lock = mgr.lock().expect("lock poisond");
+        let v = lock.new_var();
+        let id = lock.var(v);
+        (
+            Bdd {
+                mgr: Some(mgr.clone()),
+                id,
+            },
+            v,
+        )
+    }
+    pub fn var(mgr: &BddRef, v: usize) -> Self {
+        let id = mgr.lock().expect("lock poisond").var(v);
+        Bdd {
+            mgr: Some(mgr.clone()),
+            id,
+        }
+    }
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    pub fn manager(&self) -> Option<&BddRef> {
+        self.mgr.as_ref()
+    }
+    pub fn is_true(&self) -> bool {
+        self.id == TRUE
+    }
+    pub fn is_false(&self) -> bool {
+        self.id == FALSE
+    }
+    fn with<R>(&self, f: impl FnOnce(&mut BddManager) -> R) -> Option<R> {
+        let mgr = self.mgr.as_ref()?;
+        let mut lock = mgr.lock().expect("lock poisond");
+        Some(f(&mut lock))
+    }
+    fn with_id(&self, f: impl FnOnce(&mut BddManager) -> u32) -> Self {
+        match self.with(f) {
+            Some(id) => Bdd {
+                mgr: self.mgr.clone(),
+                id,
+            },
+            None => self.clone(),
+        }
+    }
+    /// 別々のmanagerのnode同士は計算できないのでpanicする
+    fn binary(&self, rhs: &Self, op: Op) -> Self {
+        if let (Some(a), Some(b)) = (&self.mgr, &rhs.mgr) {
+            assert!(Arc::ptr_eq(a, b), "bdds belong to different managers");
+        }
+        match self.mgr.as_ref().or(rhs.mgr.as_ref()) {
+            Some(mgr) => {
+                let id = mgr.lock().expect("lock poisond").apply(op, self.id, rhs.id);
+                Bdd {
+                    mgr: Some(mgr.clone()),
+                    id,
+                }
+            }
+            None => {
+                let (a, b) = (self.id == TRUE, rhs.id == TRUE);
+                Self::lgc_const(match op {
+                    Op::And => a && b,
+                    Op::Or => a || b,
+                    Op::Xor => a != b,
+                })
+            }
+        }
+    }
+    pub fn restrict(&self, v: usize, val: bool) -> Self {
+        self.with_id(|m| m.restrict(self.id, v, val))
+    }
+    pub fn exists(&self, v: usize) -> Self {
+        self.with_id(|m| m.exists(self.id, v))
+    }
+    pub fn forall(&self, v: usize) -> Self {
+        self.with_id(|m| m.forall(self.id, v))
+    }
+    /// managerの全変数に対する充足割当の数。u128に収まらなければNone
+    pub fn sat_count(&self) -> Option<u128> {
+        self.with(|m| m.sat_count(self.id))
+            .unwrap_or(Some(self.id as u128))
+    }
+    pub fn any_sat(&self) -> Option<Vec<Option<bool>>> {
+        self.with(|m| m.any_sat(self.id))
+            .unwrap_or_else(|| self.is_true().then(Vec::new))
+    }
+    pub fn eval(&self, assign: &[bool]) -> bool {
+        self.with(|m| m.eval(self.id, assign))
+            .unwrap_or(self.is_true())
+    }
+    pub fn support(&self) -> Vec<usize> {
+        self.with(|m| m.support(self.id)).unwrap_or_default()
+    }
+    pub fn size(&self) -> usize {
+        self.with(|m| m.size(self.id)).unwrap_or(0)
+    }
+}
+impl PartialEq for Bdd {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+            && match (&self.mgr, &other.mgr) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                _ => true,
+            }
+    }
+}
+impl std::fmt::Debug for Bdd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Bdd({})", self.id)
+    }
+}
+impl LogicOps for Bdd {
+    fn lgc_nand(&self, rhs: &Self) -> Self {
+        self.lgc_and(rhs).lgc_not()
+    }
+    fn lgc_and(&self, rhs: &Self) -> Self {
+        self.binary(rhs, Op::And)
+    }
+    fn lgc_nor(&self, rhs: &Self) -> Self {
+        self.lgc_or(rhs).lgc_not()
+    }
+    fn lgc_or(&self, rhs: &Self) -> Self {
+        self.binary(rhs, Op::Or)
+    }
+    fn lgc_nxor(&self, rhs: &Self) -> Self {
+        self.lgc_xor(rhs).lgc_not()
+    }
+    fn lgc_xor(&self, rhs: &Self) -> Self {
+        self.binary(rhs, Op::Xor)
+    }
+    fn lgc_not(&self) -> Self {
+        self.binary(&Self::lgc_const(true), Op::Xor)
+    }
+    fn lgc_buf(&self) -> Self {
+        self.clone()
+    }
+}
+impl LogicConst for Bdd {
+    fn lgc_const(val: bool) -> Self {
+        Bdd {
+            mgr: None,
+            id: if val { TRUE } else { FALSE },
+        }
+    }
+    fn lgc_level(&self) -> Option<bool> {
+        match self.id {
+            FALSE => Some(false),
+            TRUE => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// 入力wireidの昇順に一つずつ変数を割り当てて入力にセットする
+/// 戻り値は(wireid, 変数番号)
+pub fn symbolic_inputs(
+    circuit: &mut Circuit<Bdd>,
+    mgr: &BddRef,
+) -> Result<Vec<(WireId, usize)>, &'static str> {
+    let mut vars = Vec::new();
+    for id in circuit.input_ids() {
+        let (bdd, v) = Bdd::new_var(mgr);
+        circuit.set_input(id, Box::new(bdd))?;
+        vars.push((id, v));
+    }
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identities() {
+        let mgr = BddManager::new().into_ref();
+        let (a, _) = Bdd::new_var(&mgr);
+        let (b, _) = Bdd::new_var(&mgr);
+        // ド・モルガン
+        assert_eq!(a.lgc_nand(&b), a.lgc_not().lgc_or(&b.lgc_not()));
+        assert_eq!(a.lgc_xor(&a), Bdd::lgc_const(false));
+        assert_eq!(a.lgc_or(&a.lgc_not()), Bdd::lgc_const(true));
+        assert_eq!(a.lgc_and(&b).sat_count(), Some(1));
+        assert_eq!(a.lgc_xor(&b).sat_count(), Some(2));
+        assert_eq!(a.lgc_and(&b).exists(0), b);
+    }
+
+    #[test]
+    fn sat_count_overflow() {
+        let mgr = BddManager::new().into_ref();
+        let vars: Vec<Bdd> = (0..129).map(|_| Bdd::new_var(&mgr).0).collect();
+        // 129変数のうち一つを固定すると2^128通りで収まらない
+        assert_eq!(vars[0].sat_count(), None);
+        assert_eq!(vars[0].lgc_and(&vars[1]).sat_count(), Some(1 << 127));
+    }
+
+    #[test]
+    fn unknown_var() {
+        let mgr = BddManager::new().into_ref();
+        let (a, _) = Bdd::new_var(&mgr);
+        // managerに無い変数での固定はそのまま
+        assert_eq!(a.restrict(5, true), a);
+        assert_eq!(a.exists(5), a);
+        // varは足りない変数を追加する
+        let c = Bdd::var(&mgr, 3);
+        assert_eq!(mgr.lock().unwrap().var_count(), 4);
+        assert_eq!(c.restrict(3, true), Bdd::lgc_const(true));
+        assert_eq!(a.lgc_and(&c).sat_count(), Some(4));
+    }
+
+    #[test]
+    #[should_panic]
+    fn different_managers() {
+        let (a, _) = Bdd::new_var(&BddManager::new().into_ref());
+        let (b, _) = Bdd::new_var(&BddManager::new().into_ref());
+        a.lgc_and(&b);
+    }
+
+    #[test]
+    fn full_adder() {
+        let json = include_str!("yosys_sample.v");
+        let mut circuit = Circuit::<Bdd>::from_yosys(json).unwrap();
+        let mgr = BddManager::new().into_ref();
+        symbolic_inputs(&mut circuit, &mgr).unwrap();
+        circuit.evaluate().unwrap();
+
+        let carry = circuit.netlist().port("carry").unwrap().bits[0]
+            .wireid()
+            .unwrap();
+        let carry = *circuit.get_output(carry).unwrap().unwrap();
+        // a + b >= 16 となる(a, b)は120通り
+        assert_eq!(carry.sat_count(), Some(120));
+        assert_eq!(carry.support().len(), 8);
+
+        let x0 = circuit.netlist().port("X").unwrap().bits[0]
+            .wireid()
+            .unwrap();
+        let x0 = *circuit.get_output(x0).unwrap().unwrap();
+        let a0 = Bdd::var(&mgr, 0);
+        let b0 = Bdd::var(&mgr, 4);
+        assert_eq!(x0, a0.lgc_xor(&b0));
+    }
+}
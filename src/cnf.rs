@@ -0,0 +1,261 @@
+use crate::{
+    netlist::{Bit, Netlist},
+    yosys_parse::{CellType, WireId},
+};
+use std::{collections::HashMap, fmt::Write};
+
+/// DIMACSのリテラル。負なら否定
+pub type Lit = i32;
+
+/// # Sammary
+/// 回路をTseitin変換したCNF
+/// # contents
+/// - vars: wireid -> 変数番号(1始まり)
+/// - constant: 定数1を表す変数
+pub struct Cnf {
+    pub num_vars: usize,
+    pub clauses: Vec<Vec<Lit>>,
+    vars: HashMap<WireId, Lit>,
+    constant: Option<Lit>,
+    /// 変数番号 -> 名前(ネット名やポート名)
+    names: HashMap<Lit, Vec<String>>,
+    ports: Vec<(String, Vec<Bit>)>,
+}
+impl Cnf {
+    pub fn from_netlist(netlist: &Netlist) -> Result<Self, &'static str> {
+        if !netlist.memories.is_empty() {
+            return Err("memory is not supported");
+        }
+        let mut cnf = Cnf {
+            num_vars: 0,
+            clauses: Vec::new(),
+            vars: HashMap::new(),
+            constant: None,
+            names: HashMap::new(),
+            ports: netlist
+                .ports
+                .iter()
+                .map(|p| (p.name.clone(), p.bits.clone()))
+                .collect(),
+        };
+        for cell in netlist.cells.iter() {
+            let y = cnf.lit(&Bit::Wire(cell.output));
+            let ins: Vec<Lit> = cell.inputs.iter().map(|b| cnf.lit(b)).collect();
+            let a = *ins.first().ok_or("connection less")?;
+            let b = *ins.get(1).unwrap_or(&a);
+            match cell.type_name {
+                CellType::And => cnf.and(y, a, b),
+                CellType::Nand => cnf.and(-y, a, b),
+                CellType::Or => cnf.and(-y, -a, -b),
+                CellType::Nor => cnf.and(y, -a, -b),
+                CellType::Xor => cnf.xor(y, a, b),
+                CellType::Nxor => cnf.xor(-y, a, b),
+                CellType::Not => cnf.equal(y, -a),
+                CellType::Mem | CellType::MemV2 => unreachable!(),
+            }
+        }
+        for port in netlist.ports.iter() {
+            for bit in port.bits.iter() {
+                cnf.lit(bit);
+            }
+        }
+        for (name, bits) in netlist.netnames.iter() {
+            for (i, bit) in bits.iter().enumerate() {
+                if let Bit::Wire(id) = bit {
+                    if let Some(&v) = cnf.vars.get(id) {
+                        let name = if bits.len() == 1 {
+                            name.clone()
+                        } else {
+                            format!("{}[{}]", name, i)
+                        };
+                        cnf.names.entry(v).or_default().push(name);
+                    }
+                }
+            }
+        }
+        Ok(cnf)
+    }
+    fn new_var(&mut self) -> Lit {
+        self.num_vars += 1;
+        self.num_vars as Lit
+    }
+    /// bitに対応するリテラル。なければ変数を作る
+    pub fn lit(&mut self, bit: &Bit) -> Lit {
+        match bit {
+            Bit::Wire(id) => match self.vars.get(id) {
+                Some(&v) => v,
+                None => {
+                    let v = self.new_var();
+                    self.vars.insert(*id, v);
+                    v
+                }
+            },
+            Bit::Const(b) => {
+                let t = match self.constant {
+                    Some(t) => t,
+                    None => {
+                        let t = self.new_var();
+                        self.clauses.push(vec![t]);
+                        self.names.entry(t).or_default().push("1'b1".to_string());
+                        self.constant = Some(t);
+                        t
+                    }
+                };
+                if *b {
+                    t
+                } else {
+                    -t
+                }
+            }
+        }
+    }
+    pub fn var(&self, id: WireId) -> Option<Lit> {
+        self.vars.get(&id).copied()
+    }
+    /// y <-> a & b
+    fn and(&mut self, y: Lit, a: Lit, b: Lit) {
+        self.clauses.push(vec![-y, a]);
+        self.clauses.push(vec![-y, b]);
+        self.clauses.push(vec![y, -a, -b]);
+    }
+    /// y <-> a ^ b
+    fn xor(&mut self, y: Lit, a: Lit, b: Lit) {
+        self.clauses.push(vec![-y, a, b]);
+        self.clauses.push(vec![-y, -a, -b]);
+        self.clauses.push(vec![y, -a, b]);
+        self.clauses.push(vec![y, a, -b]);
+    }
+    /// y <-> a
+    fn equal(&mut self, y: Lit, a: Lit) {
+        self.clauses.push(vec![-y, a]);
+        self.clauses.push(vec![y, -a]);
+    }
+    /// ポートのリテラル(LSB first)
+    pub fn port_lits(&mut self, name: &str) -> Result<Vec<Lit>, &'static str> {
+        let bits = self
+            .ports
+            .iter()
+            .find(|(n, _)| n == name)
+            .ok_or("selected port is not found")?
+            .1
+            .clone();
+        Ok(bits.iter().map(|b| self.lit(b)).collect())
+    }
+    /// ポートの値を固定する。Noneのbitは制約しない
+    pub fn constrain_port(
+        &mut self,
+        name: &str,
+        values: &[Option<bool>],
+    ) -> Result<(), &'static str> {
+        let lits = self.port_lits(name)?;
+        if lits.len() != values.len() {
+            return Err("width mismatch");
+        }
+        for (&l, v) in lits.iter().zip(values.iter()) {
+            if let Some(v) = v {
+                self.clauses.push(vec![if *v { l } else { -l }]);
+            }
+        }
+        Ok(())
+    }
+    /// 変数番号 -> 名前の一覧
+    pub fn var_names(&self) -> Vec<(Lit, Vec<String>)> {
+        let mut names: Vec<(Lit, Vec<String>)> =
+            self.names.iter().map(|(&v, n)| (v, n.clone())).collect();
+        names.sort();
+        names
+    }
+    /// 変数番号とポートbitの対応
+    pub fn port_map(&self) -> Vec<(String, Vec<Lit>)> {
+        self.ports
+            .iter()
+            .map(|(name, bits)| {
+                (
+                    name.clone(),
+                    bits.iter()
+                        .map(|b| match b {
+                            Bit::Wire(id) => self.vars[id],
+                            Bit::Const(c) => {
+                                let t = self.constant.unwrap_or(0);
+                                if *c {
+                                    t
+                                } else {
+                                    -t
+                                }
+                            }
+                        })
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+    /// 変数の対応をコメント(c行)に書いたDIMACS形式
+    pub fn to_dimacs(&self) -> String {
+        let mut out = String::new();
+        for (name, lits) in self.port_map() {
+            let lits: Vec<String> = lits.iter().map(|l| l.to_string()).collect();
+            writeln!(out, "c port {} {}", name, lits.join(" ")).unwrap();
+        }
+        for (v, names) in self.var_names() {
+            writeln!(out, "c var {} {}", v, names.join(" ")).unwrap();
+        }
+        writeln!(out, "p cnf {} {}", self.num_vars, self.clauses.len()).unwrap();
+        for clause in self.clauses.iter() {
+            for l in clause.iter() {
+                write!(out, "{} ", l).unwrap();
+            }
+            writeln!(out, "0").unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{netlist::Port, yosys_parse::Direction, Circuit};
+
+    #[test]
+    fn full_adder() {
+        let netlist = Netlist::from_yosys(include_str!("yosys_sample.v")).unwrap();
+        let cnf = Cnf::from_netlist(&netlist).unwrap();
+        // 全cellの出力をprobeポートに出して、Circuitで全wireの値を求める
+        let mut probe = netlist.clone();
+        probe.ports.retain(|p| p.direction == Direction::In);
+        probe.ports.push(Port {
+            name: "probe".to_string(),
+            direction: Direction::Out,
+            bits: netlist.cells.iter().map(|c| Bit::Wire(c.output)).collect(),
+        });
+        let mut circuit = Circuit::<bool>::from_netlist(probe).unwrap();
+        let ids = netlist.input_ids();
+        for pattern in 0..256u32 {
+            let mut assign = vec![false; cnf.num_vars + 1];
+            for (i, &id) in ids.iter().enumerate() {
+                let v = (pattern >> i) & 1 == 1;
+                circuit.set_input(id, Box::new(v)).unwrap();
+                assign[cnf.var(id).unwrap() as usize] = v;
+            }
+            circuit.evaluate().unwrap();
+            let values = circuit.get_port("probe").unwrap();
+            for (cell, v) in netlist.cells.iter().zip(values) {
+                assign[cnf.var(cell.output).unwrap() as usize] = v;
+            }
+            let sat = |assign: &Vec<bool>| {
+                cnf.clauses.iter().all(|c| {
+                    c.iter()
+                        .any(|&l| assign[l.unsigned_abs() as usize] == (l > 0))
+                })
+            };
+            assert!(sat(&assign));
+            // 出力を反転させると充足しない
+            let carry = cnf.var(14).unwrap() as usize;
+            assign[carry] = !assign[carry];
+            assert!(!sat(&assign));
+        }
+        let dimacs = cnf.to_dimacs();
+        assert!(dimacs.contains("c port carry "));
+        assert!(dimacs.contains(&format!("p cnf {} {}", cnf.num_vars, cnf.clauses.len())));
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_stream::Stream;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TaskIndex(usize);
+
+/// # Sammary
+/// 依存関係のあるfutureの集まり
+/// execute()のStreamをpollすると、依存先がすべて終わったfutureから順にtokio::spawnする
+pub struct TaskGraph<F> {
+    tasks: Vec<F>,
+    /// tasks[i]の終了を待つtaskのindex
+    readers: Vec<Vec<usize>>,
+    /// tasks[i]が待つtaskの数
+    deps: Vec<usize>,
+}
+impl<F> TaskGraph<F>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    pub fn new() -> Self {
+        TaskGraph {
+            tasks: Vec::new(),
+            readers: Vec::new(),
+            deps: Vec::new(),
+        }
+    }
+    pub fn add_task(&mut self, task: F) -> Result<TaskIndex, &'static str> {
+        self.tasks.push(task);
+        self.readers.push(Vec::new());
+        self.deps.push(0);
+        Ok(TaskIndex(self.tasks.len() - 1))
+    }
+    /// fromがすべて終わってからtoを始める
+    pub fn add_deps(&mut self, from: &[TaskIndex], to: &TaskIndex) -> Result<(), &'static str> {
+        if from.iter().chain([to]).any(|t| t.0 >= self.tasks.len()) {
+            return Err("task is not found");
+        }
+        for f in from.iter() {
+            self.readers[f.0].push(to.0);
+            self.deps[to.0] += 1;
+        }
+        Ok(())
+    }
+    /// taskが一つ終わるたびに()を流すStream
+    /// 依存がループしていると終わらない
+    pub fn execute(self) -> ((), Execution<F>) {
+        let (tx, rx) = unbounded_channel();
+        let exec = Execution {
+            tasks: self.tasks.into_iter().map(Some).collect(),
+            readers: self.readers,
+            deps: self.deps,
+            started: false,
+            done: 0,
+            tx,
+            rx,
+        };
+        ((), exec)
+    }
+}
+impl<F> Default for TaskGraph<F>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Execution<F> {
+    tasks: Vec<Option<F>>,
+    readers: Vec<Vec<usize>>,
+    deps: Vec<usize>,
+    started: bool,
+    done: usize,
+    tx: UnboundedSender<Result<usize, ()>>,
+    rx: UnboundedReceiver<Result<usize, ()>>,
+}
+/// Fはspawnするときに取り出すだけで、その場でpollしない
+impl<F> Unpin for Execution<F> {}
+impl<F> Execution<F>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    fn spawn(&mut self, i: usize) {
+        let task = self.tasks[i].take().unwrap();
+        let notify = Notify(i, self.tx.clone());
+        tokio::spawn(async move {
+            task.await;
+            drop(notify);
+        });
+    }
+}
+impl<F> Stream for Execution<F>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    type Item = ();
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+        let this = self.get_mut();
+        if !this.started {
+            this.started = true;
+            for i in 0..this.tasks.len() {
+                if this.deps[i] == 0 {
+                    this.spawn(i);
+                }
+            }
+        }
+        if this.done == this.tasks.len() {
+            return Poll::Ready(None);
+        }
+        match this.rx.poll_recv(cx) {
+            Poll::Ready(Some(Ok(i))) => {
+                this.done += 1;
+                for k in 0..this.readers[i].len() {
+                    let r = this.readers[i][k];
+                    this.deps[r] -= 1;
+                    if this.deps[r] == 0 {
+                        this.spawn(r);
+                    }
+                }
+                Poll::Ready(Some(()))
+            }
+            Poll::Ready(_) => panic!("task panicked"),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// taskが終わるとindexを送る。panicで落ちたときはErrを送る
+struct Notify(usize, UnboundedSender<Result<usize, ()>>);
+impl Drop for Notify {
+    fn drop(&mut self) {
+        let msg = if std::thread::panicking() {
+            Err(())
+        } else {
+            Ok(self.0)
+        };
+        let _ = self.1.send(msg);
+    }
+}
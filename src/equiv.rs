@@ -0,0 +1,347 @@
+use crate::{
+    bdd::{symbolic_inputs, BddManager},
+    cnf::Cnf,
+    netlist::{Bit, Cell, Netlist, Port, PortValues},
+    sat::{port_assignment, SatResult, Solver},
+    util::XorShift,
+    yosys_parse::{CellType, Direction, WireId},
+    Circuit,
+};
+use std::collections::HashMap;
+
+/// 入力の組とそのとき値が食い違う出力ポート
+#[derive(Debug, Clone, PartialEq)]
+pub struct Counterexample {
+    /// (ポート名, LSB firstの値)
+    pub inputs: Vec<(String, Vec<bool>)>,
+    pub outputs: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Equivalence {
+    Equivalent,
+    NotEquivalent(Counterexample),
+    /// SATソルバが衝突回数の上限に達した
+    Unknown,
+}
+
+pub struct EquivOptions {
+    /// 入力bit数がこれ以下ならBDDで厳密に判定する
+    /// BDDのnode数には上限がないので、乗算器などで爆発しない程度に小さくしておく
+    pub bdd_max_inputs: usize,
+    /// ランダムシミュレーションの回数(1回64パターン)
+    pub random_rounds: usize,
+    pub seed: u64,
+    /// SATソルバの衝突回数の上限。Noneなら無制限
+    pub sat_conflict_limit: Option<u64>,
+}
+impl Default for EquivOptions {
+    fn default() -> Self {
+        EquivOptions {
+            bdd_max_inputs: 16,
+            random_rounds: 256,
+            seed: 0x2545_f491_4f6c_dd1d,
+            sat_conflict_limit: Some(1_000_000),
+        }
+    }
+}
+
+pub const MITER_OUTPUT: &str = "miter";
+
+/// # Sammary
+/// 二つの回路のミター
+/// 入力ポートを名前で共有し、同名の出力ポートのbit毎のXORをORでまとめてMITER_OUTPUTに出す
+/// bのwireidはaと重ならないようにずらす
+pub fn miter(a: &Netlist, b: &Netlist) -> Result<Netlist, &'static str> {
+    if !a.memories.is_empty() || !b.memories.is_empty() {
+        return Err("memory is not supported");
+    }
+    if a.port(MITER_OUTPUT).is_some() || b.port(MITER_OUTPUT).is_some() {
+        return Err("port name collides with the miter output");
+    }
+    check_ports(a, b)?;
+    check_ports(b, a)?;
+
+    let shift = a.max_wireid();
+    let mut next = shift + b.max_wireid() + 1;
+    let mut new_wire = || {
+        next += 1;
+        next
+    };
+
+    // bの入力はaの同名ポートにつなぐ
+    let mut map: HashMap<WireId, Bit> = HashMap::new();
+    for port in b.input_ports() {
+        let shared = &a.port(&port.name).unwrap().bits;
+        for (bb, ab) in port.bits.iter().zip(shared.iter()) {
+            if let Bit::Wire(id) = bb {
+                map.insert(*id, *ab);
+            }
+        }
+    }
+    let map_bit = |bit: &Bit| -> Bit {
+        match bit {
+            Bit::Wire(id) => *map.get(id).unwrap_or(&Bit::Wire(id + shift)),
+            c => *c,
+        }
+    };
+
+    let mut cells: Vec<Cell> = a
+        .cells
+        .iter()
+        .map(|c| Cell {
+            name: format!("a/{}", c.name),
+            ..c.clone()
+        })
+        .collect();
+    for c in b.cells.iter() {
+        cells.push(Cell {
+            name: format!("b/{}", c.name),
+            type_name: c.type_name,
+            inputs: c.inputs.iter().map(map_bit).collect(),
+            output: c.output + shift,
+        });
+    }
+
+    let mut diffs = Vec::new();
+    for port in a.output_ports() {
+        let other = &b.port(&port.name).unwrap().bits;
+        for (i, (ab, bb)) in port.bits.iter().zip(other.iter()).enumerate() {
+            let out = new_wire();
+            cells.push(Cell {
+                name: format!("miter/{}[{}]", port.name, i),
+                type_name: CellType::Xor,
+                inputs: vec![*ab, map_bit(bb)],
+                output: out,
+            });
+            diffs.push(out);
+        }
+    }
+    let mut acc = *diffs.first().ok_or("no output to compare")?;
+    for (i, &d) in diffs.iter().enumerate().skip(1) {
+        let out = new_wire();
+        cells.push(Cell {
+            name: format!("miter/or{}", i),
+            type_name: CellType::Or,
+            inputs: vec![Bit::Wire(acc), Bit::Wire(d)],
+            output: out,
+        });
+        acc = out;
+    }
+
+    let mut ports: Vec<Port> = a.input_ports().cloned().collect();
+    ports.push(Port {
+        name: MITER_OUTPUT.to_string(),
+        direction: Direction::Out,
+        bits: vec![Bit::Wire(acc)],
+    });
+    ports.sort_by(|x, y| x.name.cmp(&y.name));
+    let mut netnames: Vec<(String, Vec<Bit>)> = a
+        .netnames
+        .iter()
+        .map(|(n, bits)| (format!("a/{}", n), bits.clone()))
+        .chain(
+            b.netnames
+                .iter()
+                .map(|(n, bits)| (format!("b/{}", n), bits.iter().map(map_bit).collect())),
+        )
+        .collect();
+    netnames.push((MITER_OUTPUT.to_string(), vec![Bit::Wire(acc)]));
+
+    Ok(Netlist {
+        name: format!("miter_{}_{}", a.name, b.name),
+        ports,
+        cells,
+        memories: Vec::new(),
+        netnames,
+    })
+}
+
+/// aのポートがbに同じ向き・幅であるか
+fn check_ports(a: &Netlist, b: &Netlist) -> Result<(), &'static str> {
+    for port in a.ports.iter() {
+        let other = b.port(&port.name).ok_or("port names do not match")?;
+        if other.direction != port.direction {
+            return Err("port directions do not match");
+        }
+        if other.bits.len() != port.bits.len() {
+            return Err("port widths do not match");
+        }
+    }
+    Ok(())
+}
+
+/// # Sammary
+/// 組み合わせ回路の等価性判定
+/// 入力が少なければBDD、多ければランダムなbit並列シミュレーションで反例を探し、
+/// 見つからなければミターのCNFをSATソルバで解く
+pub fn equiv(
+    a: &Netlist,
+    b: &Netlist,
+    options: &EquivOptions,
+) -> Result<Equivalence, &'static str> {
+    let miter = miter(a, b)?;
+    let width: usize = miter.input_ports().map(|p| p.bits.len()).sum();
+    let inputs = if width <= options.bdd_max_inputs {
+        match equiv_bdd(&miter)? {
+            Some(inputs) => inputs,
+            None => return Ok(Equivalence::Equivalent),
+        }
+    } else if let Some(inputs) = equiv_random(&miter, options)? {
+        inputs
+    } else {
+        let mut cnf = Cnf::from_netlist(&miter)?;
+        cnf.constrain_port(MITER_OUTPUT, &[Some(true)])?;
+        let mut solver = Solver::from_cnf(&cnf);
+        solver.conflict_limit = options.sat_conflict_limit;
+        match solver.solve() {
+            SatResult::Sat(model) => port_assignment(&miter, &cnf, &model)
+                .into_iter()
+                .filter(|(name, _)| name != MITER_OUTPUT)
+                .collect(),
+            SatResult::Unsat => return Ok(Equivalence::Equivalent),
+            SatResult::Unknown => return Ok(Equivalence::Unknown),
+        }
+    };
+    Ok(Equivalence::NotEquivalent(Counterexample {
+        outputs: diff_outputs(a, b, &inputs)?,
+        inputs,
+    }))
+}
+
+/// ミター出力が1になる入力。なければNone
+fn equiv_bdd(miter: &Netlist) -> Result<Option<PortValues>, &'static str> {
+    let mut circuit = Circuit::from_netlist(miter.clone()).ok_or("cannot build circuit")?;
+    let mgr = BddManager::new().into_ref();
+    let vars: HashMap<WireId, usize> = symbolic_inputs(&mut circuit, &mgr)?.into_iter().collect();
+    circuit.evaluate()?;
+    let out = circuit.get_port(MITER_OUTPUT)?.remove(0);
+    let assign = match out.any_sat() {
+        Some(assign) => assign,
+        None => return Ok(None),
+    };
+    Ok(Some(port_values(miter, |id| {
+        assign.get(vars[&id]).copied().flatten().unwrap_or(false)
+    })))
+}
+
+fn equiv_random(
+    miter: &Netlist,
+    options: &EquivOptions,
+) -> Result<Option<PortValues>, &'static str> {
+    let mut circuit = Circuit::<u64>::from_netlist(miter.clone()).ok_or("cannot build circuit")?;
+    let mut rng = XorShift(options.seed | 1);
+    for _ in 0..options.random_rounds {
+        let pattern: HashMap<WireId, u64> = circuit
+            .input_ids()
+            .into_iter()
+            .map(|id| (id, rng.next()))
+            .collect();
+        for (&id, &val) in pattern.iter() {
+            circuit.set_input(id, Box::new(val))?;
+        }
+        circuit.evaluate()?;
+        let out = circuit.get_port(MITER_OUTPUT)?[0];
+        if out != 0 {
+            let lane = out.trailing_zeros();
+            return Ok(Some(port_values(miter, |id| {
+                (pattern[&id] >> lane) & 1 == 1
+            })));
+        }
+    }
+    Ok(None)
+}
+
+fn port_values(netlist: &Netlist, f: impl Fn(WireId) -> bool) -> PortValues {
+    netlist
+        .input_ports()
+        .map(|p| {
+            (
+                p.name.clone(),
+                p.bits
+                    .iter()
+                    .map(|b| match b {
+                        Bit::Wire(id) => f(*id),
+                        Bit::Const(c) => *c,
+                    })
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+/// 反例を元の二つの回路に流して値の違う出力ポートを調べる
+fn diff_outputs(
+    a: &Netlist,
+    b: &Netlist,
+    inputs: &[(String, Vec<bool>)],
+) -> Result<Vec<String>, &'static str> {
+    let mut ca = Circuit::<bool>::from_netlist(a.clone()).ok_or("cannot build circuit")?;
+    let mut cb = Circuit::<bool>::from_netlist(b.clone()).ok_or("cannot build circuit")?;
+    for (name, values) in inputs.iter() {
+        ca.set_port(name, values.clone())?;
+        cb.set_port(name, values.clone())?;
+    }
+    ca.evaluate()?;
+    cb.evaluate()?;
+    let mut diffs = Vec::new();
+    for port in a.output_ports() {
+        if ca.get_port(&port.name)? != cb.get_port(&port.name)? {
+            diffs.push(port.name.clone());
+        }
+    }
+    Ok(diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_adder_variants() {
+        let a = Netlist::from_yosys(include_str!("yosys_sample.v")).unwrap();
+        let b = Netlist::from_yosys(include_str!("../yosys/sample/ripple_res.json")).unwrap();
+        let options = EquivOptions::default();
+        assert_eq!(equiv(&a, &b, &options), Ok(Equivalence::Equivalent));
+
+        // 桁上げの(A&B)|(P&C)の二項は同時に1にならないのでXORでも等価
+        let mut xor = b.clone();
+        for cell in xor.cells.iter_mut() {
+            if cell.type_name == CellType::Or {
+                cell.type_name = CellType::Xor;
+            }
+        }
+        assert_eq!(equiv(&a, &xor, &options), Ok(Equivalence::Equivalent));
+
+        let mut broken = b.clone();
+        let cell = broken.cells.iter_mut().find(|c| c.output == 14).unwrap();
+        cell.type_name = CellType::And;
+        let sat_only = || EquivOptions {
+            bdd_max_inputs: 0,
+            random_rounds: 0,
+            ..Default::default()
+        };
+        assert_eq!(equiv(&a, &xor, &sat_only()), Ok(Equivalence::Equivalent));
+        for options in [
+            options,
+            EquivOptions {
+                bdd_max_inputs: 0,
+                ..Default::default()
+            },
+            sat_only(),
+        ] {
+            match equiv(&a, &broken, &options).unwrap() {
+                Equivalence::NotEquivalent(cex) => assert_eq!(cex.outputs, vec!["carry"]),
+                res => panic!("{:?}", res),
+            }
+        }
+
+        // ミターの出力と同じ名前のポートがあると区別できない
+        let mut named = a.clone();
+        named.ports.iter_mut().find(|p| p.name == "X").unwrap().name = MITER_OUTPUT.to_string();
+        assert_eq!(
+            miter(&named, &named).err(),
+            Some("port name collides with the miter output")
+        );
+    }
+}
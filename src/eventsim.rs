@@ -0,0 +1,392 @@
+use crate::{
+    netlist::{Bit, Cell, Netlist},
+    timing::DelayTable,
+    yosys_parse::WireId,
+};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap},
+    fmt::Write,
+};
+
+/// 遅延の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DelayMode {
+    /// 入力の変化をそのまま遅らせて出す
+    Transport,
+    /// 遅延より短いパルスは出力に現れない
+    Inertial,
+}
+
+/// BinaryHeapに入れるための全順序付きの時刻
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Time(f64);
+impl Eq for Time {}
+impl PartialOrd for Time {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Time {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// 出力ポートのbitが落ち着くまでに余分に変化したこと
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glitch {
+    pub endpoint: String,
+    /// ベクタを与えてからの変化の回数
+    pub transitions: usize,
+    /// 最初と最後の変化の時刻
+    pub start: f64,
+    pub end: f64,
+}
+
+/// # Sammary
+/// 遅延つきのイベント駆動シミュレーション
+/// # contents
+/// - values: 各wireの現在の値
+/// - scheduled: wire毎の最後に予約した(イベント番号, 値)。慣性遅延での取り消しに使う
+/// - waves: 各wireの(時刻, 値)の変化の列
+pub struct EventSim {
+    netlist: Netlist,
+    delays: DelayTable,
+    mode: DelayMode,
+    /// wireid -> それを入力に持つcellのindex
+    fanouts: HashMap<WireId, Vec<usize>>,
+    values: HashMap<WireId, bool>,
+    scheduled: HashMap<WireId, (u64, bool)>,
+    queue: BinaryHeap<Reverse<(Time, u64, WireId, bool)>>,
+    seq: u64,
+    now: f64,
+    waves: HashMap<WireId, Vec<(f64, bool)>>,
+}
+impl EventSim {
+    /// 入力をすべて0にして落ち着いた状態から始める
+    pub fn new(
+        netlist: Netlist,
+        delays: DelayTable,
+        mode: DelayMode,
+    ) -> Result<Self, &'static str> {
+        if !netlist.memories.is_empty() {
+            return Err("memory is not supported");
+        }
+        let mut fanouts: HashMap<WireId, Vec<usize>> = HashMap::new();
+        for (i, cell) in netlist.cells.iter().enumerate() {
+            for id in cell.inputs.iter().filter_map(Bit::wireid) {
+                fanouts.entry(id).or_default().push(i);
+            }
+        }
+        let mut values: HashMap<WireId, bool> = netlist
+            .input_ids()
+            .into_iter()
+            .map(|id| (id, false))
+            .collect();
+        for i in netlist.topo_order()? {
+            let cell = &netlist.cells[i];
+            let v = Self::eval(cell, &values)?;
+            values.insert(cell.output, v);
+        }
+        let waves = values
+            .iter()
+            .map(|(&id, &v)| (id, vec![(0.0, v)]))
+            .collect();
+        Ok(EventSim {
+            netlist,
+            delays,
+            mode,
+            fanouts,
+            values,
+            scheduled: HashMap::new(),
+            queue: BinaryHeap::new(),
+            seq: 0,
+            now: 0.0,
+            waves,
+        })
+    }
+    fn eval(cell: &Cell, values: &HashMap<WireId, bool>) -> Result<bool, &'static str> {
+        let ins: Vec<bool> = cell
+            .inputs
+            .iter()
+            .map(|b| match b {
+                Bit::Wire(id) => values.get(id).copied().unwrap_or(false),
+                Bit::Const(c) => *c,
+            })
+            .collect();
+        cell.eval(&ins).ok_or("connection less")
+    }
+    pub fn netlist(&self) -> &Netlist {
+        &self.netlist
+    }
+    /// 直前のベクタで全wireが落ち着いた時刻
+    pub fn now(&self) -> f64 {
+        self.now
+    }
+    pub fn value(&self, id: WireId) -> Option<bool> {
+        self.values.get(&id).copied()
+    }
+    /// wireの(時刻, 値)の変化の列
+    pub fn wave(&self, id: WireId) -> &[(f64, bool)] {
+        self.waves.get(&id).map_or(&[], |w| w.as_slice())
+    }
+    fn schedule(&mut self, time: f64, id: WireId, value: bool) {
+        self.seq += 1;
+        self.scheduled.insert(id, (self.seq, value));
+        self.queue.push(Reverse((Time(time), self.seq, id, value)));
+    }
+    /// # Sammary
+    /// 現在時刻に入力ポートの値を変え、イベントがなくなるまで進める
+    /// inputsは(ポート名, LSB firstの値)。戻り値はこのベクタで出力ポートに出たグリッチ
+    pub fn apply(&mut self, inputs: &[(String, Vec<bool>)]) -> Result<Vec<Glitch>, &'static str> {
+        let start = self.now;
+        // 出力の波形のうちこのベクタで増えた分だけを見る
+        let marks: HashMap<WireId, usize> = self
+            .netlist
+            .output_ids()
+            .into_iter()
+            .map(|id| (id, self.wave(id).len()))
+            .collect();
+        for (name, values) in inputs.iter() {
+            let port = self
+                .netlist
+                .port(name)
+                .ok_or("selected port is not found")?;
+            if port.bits.len() != values.len() {
+                return Err("width mismatch");
+            }
+            let changes: Vec<(WireId, bool)> = port
+                .bits
+                .iter()
+                .zip(values.iter())
+                .filter_map(|(b, &v)| b.wireid().map(|id| (id, v)))
+                .collect();
+            for (id, v) in changes {
+                self.schedule(start, id, v);
+            }
+        }
+
+        while let Some(Reverse((Time(t), _, _, _))) = self.queue.peek().copied() {
+            // 同じ時刻のイベントをまとめて反映してから、影響するcellを計算する
+            let mut touched = Vec::new();
+            while let Some(&Reverse((Time(et), seq, id, v))) = self.queue.peek() {
+                if et != t {
+                    break;
+                }
+                self.queue.pop();
+                let latest = self.scheduled.get(&id).map(|&(s, _)| s) == Some(seq);
+                if latest {
+                    self.scheduled.remove(&id);
+                } else if self.mode == DelayMode::Inertial {
+                    // 取り消されたイベント
+                    continue;
+                }
+                if self.values.get(&id) != Some(&v) {
+                    self.values.insert(id, v);
+                    self.waves.entry(id).or_default().push((t, v));
+                    touched.extend(self.fanouts.get(&id).into_iter().flatten().copied());
+                }
+            }
+            touched.sort_unstable();
+            touched.dedup();
+            for i in touched {
+                let cell = &self.netlist.cells[i];
+                let (out, delay) = (cell.output, self.delays.delay(cell.type_name));
+                let v = Self::eval(cell, &self.values)?;
+                let current = self.values.get(&out).copied().unwrap_or(false);
+                match self.mode {
+                    DelayMode::Transport => {
+                        let projected = self.scheduled.get(&out).map_or(current, |&(_, p)| p);
+                        if v != projected {
+                            self.schedule(t + delay, out, v);
+                        }
+                    }
+                    DelayMode::Inertial => {
+                        // 予約済みの変化は取り消して、新しい値だけを予約し直す
+                        self.scheduled.remove(&out);
+                        if v != current {
+                            self.schedule(t + delay, out, v);
+                        }
+                    }
+                }
+            }
+            self.now = t;
+        }
+        let mut glitches = Vec::new();
+        for port in self.netlist.output_ports() {
+            for (i, id) in port
+                .bits
+                .iter()
+                .enumerate()
+                .filter_map(|(i, b)| b.wireid().map(|id| (i, id)))
+            {
+                let changes = &self.wave(id)[marks[&id]..];
+                if changes.len() > 1 {
+                    glitches.push(Glitch {
+                        endpoint: port.bit_name(i),
+                        transitions: changes.len(),
+                        start: changes[0].0,
+                        end: changes[changes.len() - 1].0,
+                    });
+                }
+            }
+        }
+        Ok(glitches)
+    }
+    /// # Sammary
+    /// ネット名毎の波形をVCDにする
+    /// 遅延の単位をnsとみなし、1psの分解能で書く
+    pub fn to_vcd(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "$timescale 1ps $end").unwrap();
+        writeln!(
+            out,
+            "$scope module {} $end",
+            vcd_identifier(&self.netlist.name)
+        )
+        .unwrap();
+        let nets: Vec<&(String, Vec<Bit>)> = self.netlist.netnames.iter().collect();
+        let code = |i: usize| -> String {
+            let mut i = i;
+            let mut s = String::new();
+            loop {
+                s.push((b'!' + (i % 94) as u8) as char);
+                i /= 94;
+                if i == 0 {
+                    break s;
+                }
+            }
+        };
+        for (i, (name, bits)) in nets.iter().enumerate() {
+            writeln!(
+                out,
+                "$var wire {} {} {} $end",
+                bits.len(),
+                code(i),
+                vcd_identifier(name)
+            )
+            .unwrap();
+        }
+        writeln!(out, "$upscope $end").unwrap();
+        writeln!(out, "$enddefinitions $end").unwrap();
+
+        let mut times: Vec<i64> = self
+            .waves
+            .values()
+            .flatten()
+            .map(|(t, _)| (t * 1000.0).round() as i64)
+            .collect();
+        times.sort_unstable();
+        times.dedup();
+        // timeは増えていくだけなので、wire毎に読んだ位置と値を持って先へ進める
+        let mut cursors: HashMap<WireId, (usize, bool)> = HashMap::new();
+        let mut value_at = |bit: &Bit, time: i64| -> char {
+            let v = match bit {
+                Bit::Const(c) => *c,
+                Bit::Wire(id) => {
+                    let wave = self.wave(*id);
+                    let (pos, v) = cursors.entry(*id).or_insert((0, false));
+                    while let Some(&(t, val)) = wave.get(*pos) {
+                        if (t * 1000.0).round() as i64 > time {
+                            break;
+                        }
+                        *v = val;
+                        *pos += 1;
+                    }
+                    *v
+                }
+            };
+            if v {
+                '1'
+            } else {
+                '0'
+            }
+        };
+        let mut last: Vec<String> = vec![String::new(); nets.len()];
+        for time in times {
+            writeln!(out, "#{}", time).unwrap();
+            for (i, (_, bits)) in nets.iter().enumerate() {
+                let value: String = bits.iter().rev().map(|b| value_at(b, time)).collect();
+                if value != last[i] {
+                    if bits.len() == 1 {
+                        writeln!(out, "{}{}", value, code(i)).unwrap();
+                    } else {
+                        writeln!(out, "b{} {}", value, code(i)).unwrap();
+                    }
+                    last[i] = value;
+                }
+            }
+        }
+        out
+    }
+}
+
+/// yosysの名前をVCDの識別子にする
+/// 先頭の\を外し、英数字・_・.以外の文字は_に置き換える
+fn vcd_identifier(name: &str) -> String {
+    name.trim_start_matches('\\')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testutil::{hazard_sample, set_port_bits},
+        yosys_parse::CellType,
+    };
+
+    /// y = a & !a の静的ハザードをcarryに出す
+    fn hazard(mode: DelayMode) -> EventSim {
+        let (mut netlist, y) = hazard_sample();
+        set_port_bits(&mut netlist, "carry", vec![Bit::Wire(y)]);
+        let mut delays = DelayTable::default();
+        delays.set(CellType::Not, 2.0);
+        EventSim::new(netlist, delays, mode).unwrap()
+    }
+
+    #[test]
+    fn glitch() {
+        let rise = [("A".to_string(), vec![true, false, false, false])];
+        let mut sim = hazard(DelayMode::Transport);
+        let glitches = sim.apply(&rise).unwrap();
+        let carry = glitches.iter().find(|g| g.endpoint == "carry").unwrap();
+        assert_eq!((carry.transitions, carry.start, carry.end), (2, 1.0, 3.0));
+        assert_eq!(sim.now(), 3.0);
+        let vcd = sim.to_vcd();
+        assert!(vcd.contains("#3000"));
+        for line in vcd.lines().filter(|l| l.starts_with("$var")) {
+            let name = line.split_whitespace().nth(4).unwrap();
+            assert!(!name.contains(['\\', '$']), "{}", line);
+        }
+
+        // ANDの入力に出る幅2のパルスは、遅延1なら慣性遅延でも通り、遅延3なら消える
+        let mut sim = hazard(DelayMode::Inertial);
+        assert!(sim
+            .apply(&rise)
+            .unwrap()
+            .iter()
+            .any(|g| g.endpoint == "carry"));
+        let mut sim = hazard(DelayMode::Inertial);
+        sim.delays.set(CellType::And, 3.0);
+        let glitches = sim.apply(&rise).unwrap();
+        assert!(glitches.iter().all(|g| g.endpoint != "carry"));
+        assert_eq!(
+            sim.value(
+                sim.netlist().port("carry").unwrap().bits[0]
+                    .wireid()
+                    .unwrap()
+            ),
+            Some(false)
+        );
+    }
+}
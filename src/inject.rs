@@ -0,0 +1,347 @@
+use crate::{
+    traits::{LogicConst, LogicOps},
+    util::XorShift,
+    vectors::VectorSet,
+    Circuit, CircuitNode,
+};
+
+/// 故障注入の種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Injection {
+    /// 取り除くまで値を固定する
+    Force(bool),
+    /// 次の1回の計算だけ値を反転する
+    Flip,
+    /// 計算のたびに確率pで値を反転する
+    Random(f64),
+}
+
+/// 1回の計算でnodeの出力に施す操作
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Action {
+    Force(bool),
+    Flip,
+}
+impl Action {
+    fn value<T>(self, old: &T) -> T
+    where
+        T: LogicOps + LogicConst,
+    {
+        match self {
+            Action::Force(b) => T::lgc_const(b),
+            Action::Flip => old.lgc_not(),
+        }
+    }
+}
+
+/// 故障を注入する場所
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Site {
+    /// cellの出力かメモリの読み出しデータを計算するnode
+    Node(usize),
+    /// input[k]。計算の間だけ入力の値を書き換える
+    Input(usize),
+}
+
+impl<T> CircuitNode<T> {
+    /// culc()が書き込んだ出力を書き換える
+    pub(crate) fn apply(&self, action: Action) -> Result<(), &'static str>
+    where
+        T: LogicOps + LogicConst,
+    {
+        let out = self.out();
+        let val = {
+            let wire = out.get_out();
+            let lock = wire.read()?;
+            action.value(lock.as_deref().ok_or("output is empty")?)
+        };
+        out.write(Box::new(val))?;
+        Ok(())
+    }
+}
+
+impl<T> Circuit<T> {
+    /// ネット名("name[bit]")かcell名で、値を書き換える場所を探す
+    /// cellの出力・メモリの読み出しデータ・外部入力のネットを選べる
+    fn injection_target(&self, name: &str) -> Result<Site, &'static str> {
+        let netlist = self.netlist();
+        // nodeはcellの順に並んでいる
+        if let Some(i) = netlist.cells.iter().position(|c| c.name == name) {
+            return Ok(Site::Node(i));
+        }
+        let id = netlist
+            .find_wire(name)
+            .ok_or("selected name is not found")?;
+        if let Some(&i) = self.node_of.get(&id) {
+            return Ok(Site::Node(i));
+        }
+        self.input
+            .binary_search_by_key(&id, |(x, _)| *x)
+            .map(Site::Input)
+            .map_err(|_| "selected wire is not driven")
+    }
+    /// # Sammary
+    /// nameのnodeが出力に書き込んだ直後に値を書き換える
+    /// 外部入力のネットは計算の間だけ書き換え、次の計算の前に元の値に戻す
+    /// 同じ場所への注入は置き換える
+    pub fn inject(&mut self, name: &str, injection: Injection) -> Result<(), &'static str> {
+        let site = self.injection_target(name)?;
+        self.injections.retain(|(s, _)| *s != site);
+        self.injections.push((site, injection));
+        Ok(())
+    }
+    pub fn remove_injection(&mut self, name: &str) -> Result<(), &'static str> {
+        let site = self.injection_target(name)?;
+        self.injections.retain(|(s, _)| *s != site);
+        Ok(())
+    }
+    pub fn clear_injections(&mut self) {
+        self.injections.clear();
+    }
+    /// Random(p)の乱数の種
+    pub fn set_injection_seed(&mut self, seed: u64) {
+        self.rng = XorShift(seed | 1);
+    }
+    /// 直前の計算で実際に値を書き換えた場所の名前
+    /// cellはcell名、それ以外はネット名
+    pub fn fired_injections(&self) -> Vec<String> {
+        let netlist = self.netlist();
+        self.fired
+            .iter()
+            .filter_map(|&site| {
+                let id = match site {
+                    Site::Node(i) if i < netlist.cells.len() => {
+                        return Some(netlist.cells[i].name.clone())
+                    }
+                    Site::Node(i) => *self.node_of.iter().find(|(_, &n)| n == i)?.0,
+                    Site::Input(k) => self.input[k].0,
+                };
+                netlist.wire_name(id)
+            })
+            .collect()
+    }
+    /// # Sammary
+    /// 今回の計算で各nodeに施す操作を決める。Flipはここで取り除く
+    /// 前回書き換えた入力は元に戻してから、今回の分を書き換える
+    pub(crate) fn next_actions(&mut self) -> Result<Vec<Option<Action>>, &'static str>
+    where
+        T: LogicOps + LogicConst,
+    {
+        for (k, held) in self.held.iter_mut().enumerate() {
+            if let Some(val) = held.take() {
+                self.input[k].1.write(val)?;
+                self.dirty[k] = true;
+            }
+        }
+        let mut actions = vec![None; self.nodes.len()];
+        self.fired.clear();
+        for &(site, injection) in self.injections.iter() {
+            let action = match injection {
+                Injection::Force(b) => Action::Force(b),
+                Injection::Flip => Action::Flip,
+                Injection::Random(p) => {
+                    let r = (self.rng.next() >> 11) as f64 / (1u64 << 53) as f64;
+                    if r >= p {
+                        continue;
+                    }
+                    Action::Flip
+                }
+            };
+            match site {
+                Site::Node(i) => actions[i] = Some(action),
+                Site::Input(k) => {
+                    let wire = &self.input[k].1;
+                    // 値の入っていない入力はそのまま計算させてErrにする
+                    let val = match wire.get_out().read()?.as_deref() {
+                        Some(old) => action.value(old),
+                        None => continue,
+                    };
+                    self.held[k] = wire.write(Box::new(val))?;
+                    self.dirty[k] = true;
+                }
+            }
+            self.fired.push(site);
+        }
+        self.injections.retain(|(_, i)| *i != Injection::Flip);
+        Ok(actions)
+    }
+}
+
+/// # contents
+/// - injections: 実際に値を書き換えた計算の回数
+/// - propagated: 出力ポート毎の、値が正常時と変わった回数
+/// - masked: どの出力にも現れなかった回数
+#[derive(Debug, Clone, Default)]
+pub struct CampaignReport {
+    pub injections: usize,
+    pub propagated: Vec<(String, usize)>,
+    pub masked: usize,
+}
+
+/// # Sammary
+/// ベクタ毎に正常時の出力を求めたあと、targetsの一つずつにinjectionを注入して計算し直し、
+/// 出力ポートのどれに影響したかを数える
+pub fn campaign<T>(
+    circuit: &mut Circuit<T>,
+    vectors: &VectorSet,
+    targets: &[String],
+    injection: Injection,
+) -> Result<CampaignReport, &'static str>
+where
+    T: LogicOps + LogicConst + PartialEq,
+{
+    let outputs: Vec<String> = circuit
+        .netlist()
+        .output_ports()
+        .map(|p| p.name.clone())
+        .collect();
+    let mut report = CampaignReport {
+        propagated: outputs.iter().map(|n| (n.clone(), 0)).collect(),
+        ..Default::default()
+    };
+    let saved = std::mem::take(&mut circuit.injections);
+    let mut count = || -> Result<(), &'static str> {
+        for pattern in vectors.patterns.iter() {
+            let golden = run(circuit, &vectors.ports, pattern, &outputs)?;
+            for target in targets.iter() {
+                circuit.inject(target, injection)?;
+                let res = run(circuit, &vectors.ports, pattern, &outputs);
+                circuit.clear_injections();
+                let res = res?;
+                if circuit.fired.is_empty() {
+                    continue;
+                }
+                report.injections += 1;
+                let mut visible = false;
+                for (i, (g, r)) in golden.iter().zip(res.iter()).enumerate() {
+                    if g != r {
+                        report.propagated[i].1 += 1;
+                        visible = true;
+                    }
+                }
+                if !visible {
+                    report.masked += 1;
+                }
+            }
+        }
+        Ok(())
+    };
+    let res = count();
+    // 途中で失敗しても呼び出し前の注入に戻す
+    circuit.injections = saved;
+    res.map(|()| report)
+}
+
+fn run<T>(
+    circuit: &mut Circuit<T>,
+    ports: &[String],
+    pattern: &[Vec<bool>],
+    outputs: &[String],
+) -> Result<Vec<Vec<T>>, &'static str>
+where
+    T: LogicOps + LogicConst,
+{
+    for (name, values) in ports.iter().zip(pattern.iter()) {
+        circuit.set_port(name, values.iter().map(|&b| T::lgc_const(b)).collect())?;
+    }
+    circuit.evaluate()?;
+    let mut res = Vec::new();
+    for name in outputs.iter() {
+        res.push(circuit.get_port(name)?);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inject() {
+        let mut circuit = Circuit::<bool>::from_yosys(include_str!("yosys_sample.v")).unwrap();
+        let ports = ["A".to_string(), "B".to_string()];
+        let pattern = [vec![false; 4], vec![false; 4]];
+        let outputs = ["X".to_string()];
+        let x0 = circuit.netlist().port("X").unwrap().bits[0]
+            .wireid()
+            .unwrap();
+        let x0 = circuit.netlist().wire_name(x0).unwrap();
+
+        circuit.inject(&x0, Injection::Flip).unwrap();
+        let x = run(&mut circuit, &ports, &pattern, &outputs)
+            .unwrap()
+            .remove(0);
+        assert_eq!(x, vec![true, false, false, false]);
+        assert_eq!(circuit.fired_injections().len(), 1);
+        // Flipは1回きり
+        let x = run(&mut circuit, &ports, &pattern, &outputs)
+            .unwrap()
+            .remove(0);
+        assert_eq!(x, vec![false; 4]);
+        assert!(circuit.fired_injections().is_empty());
+
+        circuit.inject(&x0, Injection::Force(true)).unwrap();
+        for _ in 0..2 {
+            assert!(run(&mut circuit, &ports, &pattern, &outputs).unwrap()[0][0]);
+        }
+        circuit.remove_injection(&x0).unwrap();
+        assert!(!run(&mut circuit, &ports, &pattern, &outputs).unwrap()[0][0]);
+
+        // 入力のネットは計算の間だけ書き換わる
+        circuit.inject("A[0]", Injection::Flip).unwrap();
+        circuit.evaluate().unwrap();
+        assert_eq!(
+            circuit.get_port("X").unwrap(),
+            vec![true, false, false, false]
+        );
+        assert_eq!(circuit.fired_injections(), vec!["A[0]".to_string()]);
+        circuit.evaluate().unwrap();
+        assert_eq!(circuit.get_port("X").unwrap(), vec![false; 4]);
+        assert!(circuit.inject("nothing", Injection::Flip).is_err());
+    }
+
+    #[test]
+    fn inject_memory_data() {
+        let json = include_str!("../yosys/sample/ram_res.json");
+        let mut circuit = Circuit::<bool>::from_yosys(json).unwrap();
+        let id = circuit.netlist().memories[0].read_ports[0].data[0];
+        let name = circuit.netlist().wire_name(id).unwrap();
+        for port in ["raddr", "we", "waddr", "wdata", "clk"] {
+            let width = circuit.netlist().port(port).unwrap().bits.len();
+            circuit.set_port(port, vec![false; width]).unwrap();
+        }
+        // アドレス0のINITは1なので、読み出しデータのbit0を0に固定する
+        circuit.inject(&name, Injection::Force(false)).unwrap();
+        circuit.evaluate().unwrap();
+        assert_eq!(circuit.peek(id).unwrap(), Some(false));
+        assert_eq!(circuit.fired_injections(), vec![name.clone()]);
+        circuit.remove_injection(&name).unwrap();
+        circuit.evaluate().unwrap();
+        assert_eq!(circuit.peek(id).unwrap(), Some(true));
+    }
+
+    #[test]
+    fn campaign_counts() {
+        let mut circuit = Circuit::<bool>::from_yosys(include_str!("yosys_sample.v")).unwrap();
+        let targets: Vec<String> = circuit
+            .netlist()
+            .cells
+            .iter()
+            .map(|c| c.name.clone())
+            .collect();
+        let vectors = VectorSet::parse("A B\n0000 0000\n1111 0001\n").unwrap();
+        let report = campaign(&mut circuit, &vectors, &targets, Injection::Flip).unwrap();
+        assert_eq!(report.injections, targets.len() * 2);
+        let propagated: usize = report.propagated.iter().map(|(_, n)| n).sum();
+        assert!(propagated >= report.injections - report.masked);
+
+        let report = campaign(&mut circuit, &vectors, &targets, Injection::Random(0.0)).unwrap();
+        assert_eq!(report.injections, 0);
+
+        // 失敗しても元の注入は残る
+        circuit.inject(&targets[0], Injection::Force(true)).unwrap();
+        let missing = ["nothing".to_string()];
+        assert!(campaign(&mut circuit, &vectors, &missing, Injection::Flip).is_err());
+        assert_eq!(circuit.injections.len(), 1);
+    }
+}
@@ -1,21 +1,52 @@
-#![feature(async_stream)]
 extern crate num;
 extern crate serde;
-extern crate tokio_dagtask;
 
+pub mod aig;
+pub mod atpg;
+pub mod bdd;
+pub mod cnf;
+#[cfg(feature = "async")]
+mod dagtask;
+pub mod equiv;
+pub mod eventsim;
+pub mod fault;
+pub mod inject;
+pub mod minimize;
+pub mod netlist;
+pub mod opt;
+pub mod parallel;
+pub mod power;
+pub mod prob;
+pub mod sat;
+pub mod slice;
+pub mod stats;
+pub mod techmap;
+#[cfg(test)]
+mod testutil;
+pub mod timing;
+pub mod util;
+pub mod vectors;
 pub mod yosys_parse;
 
+#[cfg(feature = "async")]
+use dagtask::TaskGraph;
+use inject::{Action, Injection, Site};
+use netlist::{Bit, MemoryCell, Netlist};
 use std::{
     collections::HashMap,
-    future::Future,
-    pin::Pin,
-    sync::{Arc, RwLock, RwLockReadGuard},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock, RwLockReadGuard,
+    },
 };
-use tokio_dagtask::TaskGraph;
-use tokio_stream::{Stream, StreamExt};
-use yosys_parse::{WireId, YosysRootElem};
+#[cfg(feature = "async")]
+use std::{future::Future, pin::Pin};
+#[cfg(feature = "async")]
+use tokio_stream::StreamExt;
+use util::XorShift;
+use yosys_parse::{CellType, WireId};
 
-use traits::LogicOps;
+use traits::{LogicConst, LogicOps};
 pub mod traits {
     pub trait LogicOps {
         fn lgc_nand(&self, rhs: &Self) -> Self;
@@ -25,20 +56,148 @@ pub mod traits {
         fn lgc_nxor(&self, rhs: &Self) -> Self;
         fn lgc_xor(&self, rhs: &Self) -> Self;
         fn lgc_not(&self) -> Self;
+        /// 値をそのまま写す。既定ではNOTを二回かける
+        fn lgc_buf(&self) -> Self
+        where
+            Self: Sized,
+        {
+            self.lgc_not().lgc_not()
+        }
+    }
+    /// 定数bitとの相互変換
+    /// 定数入力やメモリのアドレス・データに使う
+    pub trait LogicConst {
+        fn lgc_const(val: bool) -> Self;
+        /// 値が0/1に確定していなければNone
+        fn lgc_level(&self) -> Option<bool>;
+    }
+
+    impl LogicOps for bool {
+        fn lgc_nand(&self, rhs: &Self) -> Self {
+            !(*self && *rhs)
+        }
+        fn lgc_and(&self, rhs: &Self) -> Self {
+            *self && *rhs
+        }
+        fn lgc_nor(&self, rhs: &Self) -> Self {
+            !(*self || *rhs)
+        }
+        fn lgc_or(&self, rhs: &Self) -> Self {
+            *self || *rhs
+        }
+        fn lgc_nxor(&self, rhs: &Self) -> Self {
+            *self == *rhs
+        }
+        fn lgc_xor(&self, rhs: &Self) -> Self {
+            *self != *rhs
+        }
+        fn lgc_not(&self) -> Self {
+            !*self
+        }
+        fn lgc_buf(&self) -> Self {
+            *self
+        }
+    }
+    impl LogicConst for bool {
+        fn lgc_const(val: bool) -> Self {
+            val
+        }
+        fn lgc_level(&self) -> Option<bool> {
+            Some(*self)
+        }
+    }
+
+    /// 64パターンを1bitずつ並べて同時に計算する
+    impl LogicOps for u64 {
+        fn lgc_nand(&self, rhs: &Self) -> Self {
+            !(*self & *rhs)
+        }
+        fn lgc_and(&self, rhs: &Self) -> Self {
+            *self & *rhs
+        }
+        fn lgc_nor(&self, rhs: &Self) -> Self {
+            !(*self | *rhs)
+        }
+        fn lgc_or(&self, rhs: &Self) -> Self {
+            *self | *rhs
+        }
+        fn lgc_nxor(&self, rhs: &Self) -> Self {
+            !(*self ^ *rhs)
+        }
+        fn lgc_xor(&self, rhs: &Self) -> Self {
+            *self ^ *rhs
+        }
+        fn lgc_not(&self) -> Self {
+            !*self
+        }
+        fn lgc_buf(&self) -> Self {
+            *self
+        }
+    }
+    impl LogicConst for u64 {
+        fn lgc_const(val: bool) -> Self {
+            if val {
+                !0
+            } else {
+                0
+            }
+        }
+        /// 全パターンで同じ値のときだけ確定する
+        fn lgc_level(&self) -> Option<bool> {
+            match *self {
+                0 => Some(false),
+                u64::MAX => Some(true),
+                _ => None,
+            }
+        }
     }
 }
+#[cfg(feature = "async")]
 type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
 
 /// # Sammary
 /// Binaryを表現するT:LogicBinaryOpsが与えられると、
-/// それを回路に則って計算する非同期プロセッサ
+/// それを回路に則って計算するプロセッサ
+/// evaluate()は現在のスレッドで、culc_async()はTaskGraphで計算する
 /// # contents
 /// - Wire: マルチスレッドでもone:multiでのイミュータブルデータ共有をする
 /// - Node: culc()でoutに計算結果を流す
+/// - Memory: tick()で書き込みと同期読み出しを行う
 pub struct Circuit<T> {
+    netlist: Netlist,
     output: Vec<(WireId, WireOut<T>)>,
     input: Vec<(WireId, WireIn<T>)>,
-    engine: TaskGraph<BoxFuture<()>>,
+    nodes: Vec<Arc<CircuitNode<T>>>,
+    /// nodes[i]が依存するnodeのindex
+    deps: Vec<Vec<usize>>,
+    /// nodes[i]の出力を読むnodeのindex
+    readers: Vec<Vec<usize>>,
+    /// depsに沿ったnodeのトポロジカル順
+    order: Vec<usize>,
+    /// 依存の深さごとのnode。同じ段のnodeは互いに依存しない
+    levels: Vec<Vec<usize>>,
+    /// input[k]を直接読むnodeのindex
+    input_readers: Vec<Vec<usize>>,
+    /// 前回の計算のあとに書き込まれたinput
+    dirty: Vec<bool>,
+    /// 値が今の入力・メモリに対応していないかもしれないnode
+    stale: Vec<bool>,
+    /// 直前の計算で実際にculc()したnodeの数
+    evaluated: Arc<AtomicUsize>,
+    memories: Vec<Arc<MemoryNode<T>>>,
+    /// 各wireidを駆動するWireOut。内部のネットの値を覗くのに使う
+    probes: HashMap<WireId, WireOut<T>>,
+    /// wireid -> それを計算するnodeのindex
+    node_of: HashMap<WireId, usize>,
+    /// 入力をそのまま出す出力のwireid -> 写すBufNodeのindex
+    buffers: HashMap<WireId, usize>,
+    /// (注入する場所, 故障注入)
+    injections: Vec<(Site, Injection)>,
+    rng: XorShift,
+    /// 直前の計算で実際に注入した場所
+    fired: Vec<Site>,
+    /// 注入で書き換えたinput[k]の元の値
+    held: Vec<Option<Box<T>>>,
 }
 impl<T> Circuit<T> {
     pub fn set_input(&mut self, id: WireId, val: Box<T>) -> Result<Option<Box<T>>, &'static str> {
@@ -46,8 +205,11 @@ impl<T> Circuit<T> {
             .input
             .binary_search_by_key(&id, |(x, _)| *x)
             .map_err(|_| "selected id is not found")?;
+        self.dirty[i] = true;
+        // 注入で書き換えている間に書き込まれたら、そちらを元の値にする
+        let held = self.held[i].take();
         let (_, wire) = self.input.get_mut(i).unwrap();
-        wire.write(val)
+        wire.write(val).map(|old| held.or(old))
     }
     pub fn get_output(&mut self, id: WireId) -> Result<Option<Box<T>>, &'static str> {
         let i = self
@@ -55,267 +217,661 @@ impl<T> Circuit<T> {
             .binary_search_by_key(&id, |(x, _)| *x)
             .map_err(|_| "selected id is not found")?;
         let (_, wire) = self.output.get_mut(i).unwrap();
-        Ok(wire.read_and_clear()?)
+        wire.read_and_clear()
+    }
+    pub fn netlist(&self) -> &Netlist {
+        &self.netlist
+    }
+    pub fn input_ids(&self) -> Vec<WireId> {
+        self.input.iter().map(|(id, _)| *id).collect()
     }
-    pub fn culc_async(self) -> BoxFuture<()>
+    pub fn output_ids(&self) -> Vec<WireId> {
+        self.output.iter().map(|(id, _)| *id).collect()
+    }
+    /// ポート名でまとめて入力する。valuesはLSB first
+    pub fn set_port(&mut self, name: &str, values: Vec<T>) -> Result<(), &'static str> {
+        let bits = self
+            .netlist
+            .port(name)
+            .ok_or("selected port is not found")?
+            .bits
+            .clone();
+        if bits.len() != values.len() {
+            return Err("width mismatch");
+        }
+        for (bit, val) in bits.iter().zip(values) {
+            if let Bit::Wire(id) = bit {
+                self.set_input(*id, Box::new(val))?;
+            }
+        }
+        Ok(())
+    }
+    /// ポート名でまとめて出力を読む。LSB first
+    pub fn get_port(&mut self, name: &str) -> Result<Vec<T>, &'static str>
     where
-        T: LogicOps + Send + Sync + 'static,
+        T: LogicConst,
     {
-        let (_, exec) = self.engine.execute();
-        Box::pin(async move {
-            exec.collect::<Vec<_>>().await;
-        })
+        let bits = self
+            .netlist
+            .port(name)
+            .ok_or("selected port is not found")?
+            .bits
+            .clone();
+        let mut values = Vec::with_capacity(bits.len());
+        for bit in bits.iter() {
+            values.push(match bit {
+                Bit::Wire(id) => *self.get_output(*id)?.ok_or("output is empty")?,
+                Bit::Const(b) => T::lgc_const(*b),
+            });
+        }
+        Ok(values)
     }
-    pub fn from_yosys(json: &str) -> Option<Self>
+    /// # Sammary
+    /// 全nodeをトポロジカル順に現在のスレッドで計算する
+    /// Tokioのランタイムがなくても使える
+    /// 値の入っていない入力があればErr
+    pub fn evaluate(&mut self) -> Result<(), &'static str>
     where
-        T: LogicOps + Send + Sync + 'static,
+        T: LogicOps + LogicConst,
     {
-        let yosys = YosysRootElem::from_json(json)?;
-        let (_, module) = yosys.modules.iter().last().unwrap();
-
-        let mut input: Vec<(WireId, WireIn<T>)> = Vec::new();
-        let mut output: Vec<(WireId, WireOut<T>)> = Vec::new();
-        let mut output_id: Vec<u32> = Vec::new();
-
-        let mut nodes: HashMap<u32, CircuitNode<T>> = HashMap::new();
-
-        // nodesにCircuitNodeをセット
-        for (_, port) in module.ports.iter() {
-            match port.direction {
-                yosys_parse::Direction::In => {
-                    for &id in port.bits.iter() {
-                        input.push((id, Default::default()));
-                    }
+        let actions = self.prepare()?;
+        self.stale.iter_mut().for_each(|s| *s = false);
+        let res = self
+            .order
+            .iter()
+            .try_for_each(|&i| culc_node(&self.nodes[i], actions[i], &self.evaluated));
+        self.settle(res)
+    }
+    /// # Sammary
+    /// idsの出力のファンインコーンにあるnodeだけを計算する
+    /// それ以外の出力は前回の値のまま(読み出し済みなら空)になる
+    pub fn evaluate_outputs(&mut self, ids: &[WireId]) -> Result<(), &'static str>
+    where
+        T: LogicOps + LogicConst,
+    {
+        let (needed, actions) = self.plan_outputs(ids)?;
+        let res = self
+            .order
+            .iter()
+            .filter(|&&i| needed[i])
+            .try_for_each(|&i| culc_node(&self.nodes[i], actions[i], &self.evaluated));
+        self.settle(res)
+    }
+    /// ポート名で指定するevaluate_outputs
+    pub fn evaluate_ports(&mut self, names: &[&str]) -> Result<(), &'static str>
+    where
+        T: LogicOps + LogicConst,
+    {
+        let ids = self.port_outputs(names)?;
+        self.evaluate_outputs(&ids)
+    }
+    /// # Sammary
+    /// 前回の計算から書き込まれた入力のファンアウトだけを計算し直す
+    /// 出力が前回と同じ値になったnodeから先へは伝播させない
+    pub fn evaluate_incremental(&mut self) -> Result<(), &'static str>
+    where
+        T: LogicOps + LogicConst + PartialEq + Clone,
+    {
+        let (needed, force, actions) = self.plan_incremental()?;
+        let changed: Vec<AtomicBool> = (0..self.nodes.len())
+            .map(|_| AtomicBool::new(false))
+            .collect();
+        let res = self
+            .order
+            .iter()
+            .filter(|&&i| needed[i])
+            .try_for_each(|&i| {
+                culc_incremental_node(
+                    &self.nodes[i],
+                    actions[i],
+                    i,
+                    &self.deps[i],
+                    force[i],
+                    &changed,
+                    &self.evaluated,
+                )
+            });
+        self.settle(res)
+    }
+    /// 直前の計算で実際にculc()したnodeの数
+    pub fn evaluated(&self) -> usize {
+        self.evaluated.load(Ordering::Relaxed)
+    }
+    /// cell・メモリの読み出しbit・入力を写す出力のnodeの数
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+    /// 計算に失敗したら、途中までの値は信用せず全nodeをstaleにする
+    fn settle(&mut self, res: Result<(), &'static str>) -> Result<(), &'static str> {
+        if res.is_err() {
+            self.stale.iter_mut().for_each(|s| *s = true);
+        }
+        res
+    }
+    /// 前回注入したnodeと書き込まれた入力をstaleにして、今回の操作を決める
+    fn prepare(&mut self) -> Result<Vec<Option<Action>>, &'static str>
+    where
+        T: LogicOps + LogicConst,
+    {
+        for &site in self.fired.iter() {
+            if let Site::Node(i) = site {
+                self.stale[i] = true;
+            }
+        }
+        // 入力への注入はここでdirtyを立てる
+        let actions = self.next_actions()?;
+        for (k, dirty) in self.dirty.iter_mut().enumerate() {
+            if std::mem::take(dirty) {
+                for &i in self.input_readers[k].iter() {
+                    self.stale[i] = true;
                 }
-                yosys_parse::Direction::Out => {
-                    for &id in port.bits.iter() {
-                        output_id.push(id);
-                    }
+            }
+        }
+        self.evaluated.store(0, Ordering::Relaxed);
+        Ok(actions)
+    }
+    /// idsのファンインコーンのnodeと今回の操作
+    /// コーンの外でコーンを読むnodeはstaleになる
+    fn plan_outputs(
+        &mut self,
+        ids: &[WireId],
+    ) -> Result<(Vec<bool>, Vec<Option<Action>>), &'static str>
+    where
+        T: LogicOps + LogicConst,
+    {
+        let mut needed = vec![false; self.nodes.len()];
+        let mut stack = Vec::new();
+        for id in ids.iter() {
+            if self.output.binary_search_by_key(id, |(x, _)| *x).is_err() {
+                return Err("selected id is not found");
+            }
+            stack.extend(self.node_of.get(id).or(self.buffers.get(id)));
+        }
+        while let Some(&i) = stack.pop() {
+            if !needed[i] {
+                needed[i] = true;
+                stack.extend(self.deps[i].iter());
+            }
+        }
+        let actions = self.prepare()?;
+        for (i, &needed_) in needed.iter().enumerate() {
+            if needed_ {
+                self.stale[i] = false;
+                for &r in self.readers[i].iter() {
+                    self.stale[r] |= !needed[r];
                 }
-                yosys_parse::Direction::InOut => {
-                    panic!("inout is not supported");
+            }
+        }
+        Ok((needed, actions))
+    }
+    /// evaluate_incrementalで計算するnodeと今回の操作
+    fn plan_incremental(&mut self) -> Result<IncrementalPlan, &'static str>
+    where
+        T: LogicOps + LogicConst,
+    {
+        let actions = self.prepare()?;
+        let n = self.nodes.len();
+        let mut force = std::mem::replace(&mut self.stale, vec![false; n]);
+        for (i, node) in self.nodes.iter().enumerate() {
+            // 注入のあるnodeと、出力を読み出されて空になったnode
+            force[i] |= actions[i].is_some() || node.out_wire().is_empty();
+        }
+        let mut needed = force.clone();
+        let mut stack: Vec<usize> = (0..n).filter(|&i| force[i]).collect();
+        while let Some(i) = stack.pop() {
+            for &r in self.readers[i].iter() {
+                if !needed[r] {
+                    needed[r] = true;
+                    stack.push(r);
                 }
             }
         }
+        Ok((needed, force, actions))
+    }
+    /// namesのポートの出力のwireid
+    fn port_outputs(&self, names: &[&str]) -> Result<Vec<WireId>, &'static str> {
+        let mut ids = Vec::new();
+        for name in names.iter() {
+            let port = self
+                .netlist
+                .port(name)
+                .ok_or("selected port is not found")?;
+            ids.extend(port.bits.iter().filter_map(Bit::wireid));
+        }
+        Ok(ids)
+    }
+    pub fn from_yosys(json: &str) -> Option<Self>
+    where
+        T: LogicOps + LogicConst,
+    {
+        Self::from_netlist(Netlist::from_yosys(json)?)
+    }
+    pub fn from_netlist(netlist: Netlist) -> Option<Self>
+    where
+        T: LogicOps + LogicConst,
+    {
+        let mut input: Vec<(WireId, WireIn<T>)> = netlist
+            .input_ids()
+            .into_iter()
+            .map(|id| (id, Default::default()))
+            .collect();
         input.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
-        for (_, cell) in module.cells.iter() {
-            let out_id = cell.output_wireid();
-            nodes.insert(
-                out_id,
-                match cell.type_name {
-                    yosys_parse::CellType::And => {
-                        CircuitNode::AndNode(None, None, Default::default())
-                    }
-                    yosys_parse::CellType::Nand => {
-                        CircuitNode::NandNode(None, None, Default::default())
-                    }
-                    yosys_parse::CellType::Or => {
-                        CircuitNode::OrNode(None, None, Default::default())
-                    }
-                    yosys_parse::CellType::Nor => {
-                        CircuitNode::NorNode(None, None, Default::default())
-                    }
-                    yosys_parse::CellType::Xor => {
-                        CircuitNode::XorNode(None, None, Default::default())
-                    }
-                    yosys_parse::CellType::Nxor => {
-                        CircuitNode::NxorNode(None, None, Default::default())
-                    }
-                    yosys_parse::CellType::Not => CircuitNode::NotNode(None, Default::default()),
-                },
-            );
+
+        // 各wireidを駆動するWireOutと、それを計算するnodeのindex
+        let mut drivers: HashMap<WireId, (WireOut<T>, Option<usize>)> = HashMap::new();
+        for (id, wire) in input.iter() {
+            drivers.insert(*id, (wire.get_out(), None));
         }
-        // 各ノードの入力部をセット
-        for (_, cell) in module.cells.iter() {
-            let out_id = cell.output_wireid();
-            let in_ids = cell.input_wireids();
-            match cell.type_name {
-                yosys_parse::CellType::And => {
-                    let in0_out = nodes.get(&in_ids[0]).map_or(
-                        input[input.binary_search_by_key(&in_ids[0], |(a, _)| *a).unwrap()]
-                            .1
-                            .get_out(),
-                        |a| a.out_wire(),
-                    );
-                    let in1_out = nodes.get(&in_ids[1]).map_or(
-                        input[input.binary_search_by_key(&in_ids[1], |(a, _)| *a).unwrap()]
-                            .1
-                            .get_out(),
-                        |a| a.out_wire(),
-                    );
-                    if let CircuitNode::AndNode(in0, in1, _) = nodes.get_mut(&out_id).unwrap() {
-                        in0.replace(in0_out);
-                        in1.replace(in1_out);
-                    } else {
-                        unreachable!()
-                    }
-                }
-                yosys_parse::CellType::Nand => {
-                    let in0_out = nodes.get(&in_ids[0]).map_or(
-                        input[input.binary_search_by_key(&in_ids[0], |(a, _)| *a).unwrap()]
-                            .1
-                            .get_out(),
-                        |a| a.out_wire(),
-                    );
-                    let in1_out = nodes.get(&in_ids[1]).map_or(
-                        input[input.binary_search_by_key(&in_ids[1], |(a, _)| *a).unwrap()]
-                            .1
-                            .get_out(),
-                        |a| a.out_wire(),
-                    );
-                    if let CircuitNode::NandNode(in0, in1, _) = nodes.get_mut(&out_id).unwrap() {
-                        in0.replace(in0_out);
-                        in1.replace(in1_out);
-                    } else {
-                        unreachable!()
-                    }
-                }
-                yosys_parse::CellType::Or => {
-                    let in0_out = nodes.get(&in_ids[0]).map_or(
-                        input[input.binary_search_by_key(&in_ids[0], |(a, _)| *a).unwrap()]
-                            .1
-                            .get_out(),
-                        |a| a.out_wire(),
-                    );
-                    let in1_out = nodes.get(&in_ids[1]).map_or(
-                        input[input.binary_search_by_key(&in_ids[1], |(a, _)| *a).unwrap()]
-                            .1
-                            .get_out(),
-                        |a| a.out_wire(),
-                    );
-                    if let CircuitNode::OrNode(in0, in1, _) = nodes.get_mut(&out_id).unwrap() {
-                        in0.replace(in0_out);
-                        in1.replace(in1_out);
-                    } else {
-                        unreachable!()
-                    }
-                }
-                yosys_parse::CellType::Nor => {
-                    let in0_out = nodes.get(&in_ids[0]).map_or(
-                        input[input.binary_search_by_key(&in_ids[0], |(a, _)| *a).unwrap()]
-                            .1
-                            .get_out(),
-                        |a| a.out_wire(),
-                    );
-                    let in1_out = nodes.get(&in_ids[1]).map_or(
-                        input[input.binary_search_by_key(&in_ids[1], |(a, _)| *a).unwrap()]
-                            .1
-                            .get_out(),
-                        |a| a.out_wire(),
-                    );
-                    if let CircuitNode::NorNode(in0, in1, _) = nodes.get_mut(&out_id).unwrap() {
-                        in0.replace(in0_out);
-                        in1.replace(in1_out);
-                    } else {
-                        unreachable!()
-                    }
-                }
-                yosys_parse::CellType::Xor => {
-                    let in0_out = nodes.get(&in_ids[0]).map_or(
-                        input[input.binary_search_by_key(&in_ids[0], |(a, _)| *a).unwrap()]
-                            .1
-                            .get_out(),
-                        |a| a.out_wire(),
-                    );
-                    let in1_out = nodes.get(&in_ids[1]).map_or(
-                        input[input.binary_search_by_key(&in_ids[1], |(a, _)| *a).unwrap()]
-                            .1
-                            .get_out(),
-                        |a| a.out_wire(),
-                    );
-                    if let CircuitNode::XorNode(in0, in1, _) = nodes.get_mut(&out_id).unwrap() {
-                        in0.replace(in0_out);
-                        in1.replace(in1_out);
-                    } else {
-                        unreachable!()
-                    }
-                }
-                yosys_parse::CellType::Nxor => {
-                    let in0_out = nodes.get(&in_ids[0]).map_or(
-                        input[input.binary_search_by_key(&in_ids[0], |(a, _)| *a).unwrap()]
-                            .1
-                            .get_out(),
-                        |a| a.out_wire(),
-                    );
-                    let in1_out = nodes.get(&in_ids[1]).map_or(
-                        input[input.binary_search_by_key(&in_ids[1], |(a, _)| *a).unwrap()]
-                            .1
-                            .get_out(),
-                        |a| a.out_wire(),
-                    );
-                    if let CircuitNode::NxorNode(in0, in1, _) = nodes.get_mut(&out_id).unwrap() {
-                        in0.replace(in0_out);
-                        in1.replace(in1_out);
-                    } else {
-                        unreachable!()
-                    }
+        let mut outs: Vec<WireIn<T>> = Vec::new();
+        for cell in netlist.cells.iter() {
+            let out = WireIn::empty_wire();
+            drivers.insert(cell.output, (out.get_out(), Some(outs.len())));
+            outs.push(out);
+        }
+        for mem in netlist.memories.iter() {
+            for port in mem.read_ports.iter() {
+                for &id in port.data.iter() {
+                    let out = WireIn::empty_wire();
+                    drivers.insert(id, (out.get_out(), Some(outs.len())));
+                    outs.push(out);
                 }
-                yosys_parse::CellType::Not => {
-                    let in0_out = nodes.get(&in_ids[0]).map_or(
-                        input[input.binary_search_by_key(&in_ids[0], |(a, _)| *a).unwrap()]
-                            .1
-                            .get_out(),
-                        |a| a.out_wire(),
-                    );
-                    if let CircuitNode::NotNode(in0, _) = nodes.get_mut(&out_id).unwrap() {
-                        in0.replace(in0_out);
-                    } else {
-                        unreachable!()
+            }
+        }
+        let source = |bit: &Bit| -> Option<(WireOut<T>, Option<usize>)> {
+            match bit {
+                Bit::Wire(id) => drivers.get(id).cloned(),
+                Bit::Const(b) => Some((WireOut::constant(T::lgc_const(*b)), None)),
+            }
+        };
+
+        // 各ノードの入力部をセット
+        let mut outs = outs.into_iter();
+        let mut nodes = Vec::new();
+        let mut deps = Vec::new();
+        let mut input_readers = vec![Vec::new(); input.len()];
+        let mut read_input = |bit: &Bit, node: usize| {
+            if let Some(k) = bit
+                .wireid()
+                .and_then(|id| input.binary_search_by_key(&id, |(x, _)| *x).ok())
+            {
+                input_readers[k].push(node);
+            }
+        };
+        for cell in netlist.cells.iter() {
+            let mut ins = Vec::new();
+            let mut dep = Vec::new();
+            for bit in cell.inputs.iter() {
+                let (wire, index) = source(bit)?;
+                ins.push(wire);
+                dep.extend(index);
+                read_input(bit, nodes.len());
+            }
+            nodes.push(Arc::new(CircuitNode::gate(
+                cell.type_name,
+                ins,
+                outs.next()?,
+            )?));
+            deps.push(dep);
+        }
+        let mut memories = Vec::new();
+        for mem in netlist.memories.iter() {
+            let node = Arc::new(MemoryNode::new(mem, &source)?);
+            for (p, port) in mem.read_ports.iter().enumerate() {
+                // 同期読み出しはtick()で確定した値を出すだけ
+                let dep: Vec<usize> = if port.clocked {
+                    Vec::new()
+                } else {
+                    port.addr
+                        .iter()
+                        .filter_map(|b| source(b).and_then(|(_, i)| i))
+                        .collect()
+                };
+                for bit in 0..port.data.len() {
+                    if !port.clocked {
+                        port.addr.iter().for_each(|b| read_input(b, nodes.len()));
                     }
+                    nodes.push(Arc::new(CircuitNode::MemReadNode(
+                        node.clone(),
+                        p,
+                        bit,
+                        outs.next()?,
+                    )));
+                    deps.push(dep.clone());
                 }
             }
+            memories.push(node);
         }
-        for out_id in output_id {
-            output.push((out_id, nodes.get(&out_id).unwrap().out_wire()));
+        // 入力をそのまま出す出力は、読み出しで入力が消えないように別のwireへ写す
+        let mut buffers = HashMap::new();
+        for out_id in netlist.output_ids() {
+            if let Some((wire, None)) = drivers.get(&out_id) {
+                let bit = Bit::Wire(out_id);
+                read_input(&bit, nodes.len());
+                buffers.insert(out_id, nodes.len());
+                nodes.push(Arc::new(CircuitNode::BufNode(
+                    Some(wire.clone()),
+                    WireIn::empty_wire(),
+                )));
+                deps.push(Vec::new());
+            }
         }
-        output.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
 
-        let mut graph = TaskGraph::new();
-        let mut mem = HashMap::new();
-        fn culc_<U: LogicOps + Send + Sync + 'static>(
-            node: CircuitNode<U>,
-        ) -> Pin<Box<dyn Future<Output = ()> + Send>> {
-            Box::pin(async move {
-                node.culc().unwrap();
-            })
+        let mut readers = vec![Vec::new(); nodes.len()];
+        for (i, dep) in deps.iter().enumerate() {
+            for &d in dep.iter() {
+                readers[d].push(i);
+            }
         }
-        for (out_id, node) in nodes.into_iter() {
-            let index = graph.add_task(culc_(node)).ok()?;
-            mem.insert(out_id, index);
-        }
-        for (_, cell) in module.cells.iter() {
-            let out_id = cell.output_wireid();
-            let in_ids = cell.input_wireids();
-            match cell.type_name {
-                yosys_parse::CellType::And
-                | yosys_parse::CellType::Nand
-                | yosys_parse::CellType::Or
-                | yosys_parse::CellType::Nor
-                | yosys_parse::CellType::Xor
-                | yosys_parse::CellType::Nxor => {
-                    let index_out = mem.get(&out_id).unwrap();
-                    if let Some(index_in0) = mem.get(&in_ids[0]) {
-                        graph.add_deps(&[index_in0.clone()], &index_out).ok()?;
-                    }
-                    if let Some(index_in1) = mem.get(&in_ids[1]) {
-                        graph.add_deps(&[index_in1.clone()], &index_out).ok()?;
-                    }
-                }
-                yosys_parse::CellType::Not => {
-                    let index_out = mem.get(&out_id).unwrap();
-                    if let Some(index_in0) = mem.get(&in_ids[0]) {
-                        graph.add_deps(&[index_in0.clone()], &index_out).ok()?;
-                    }
+        // 組み合わせループがあれば計算できない
+        let mut order = Vec::with_capacity(nodes.len());
+        let mut indeg: Vec<usize> = deps.iter().map(Vec::len).collect();
+        order.extend((0..nodes.len()).filter(|&i| indeg[i] == 0));
+        let mut head = 0;
+        while head < order.len() {
+            for &r in readers[order[head]].iter() {
+                indeg[r] -= 1;
+                if indeg[r] == 0 {
+                    order.push(r);
                 }
             }
+            head += 1;
         }
+        if order.len() != nodes.len() {
+            return None;
+        }
+        let mut depth = vec![0; nodes.len()];
+        let mut levels: Vec<Vec<usize>> = Vec::new();
+        for &i in order.iter() {
+            depth[i] = deps[i].iter().map(|&d| depth[d] + 1).max().unwrap_or(0);
+            if levels.len() == depth[i] {
+                levels.push(Vec::new());
+            }
+            levels[depth[i]].push(i);
+        }
+        let probes = drivers
+            .iter()
+            .map(|(&id, (w, _))| (id, w.clone()))
+            .collect();
+        let node_of = drivers
+            .iter()
+            .filter_map(|(&id, (_, i))| i.map(|i| (id, i)))
+            .collect();
+        let mut output: Vec<(WireId, WireOut<T>)> = Vec::new();
+        for out_id in netlist.output_ids() {
+            let wire = match buffers.get(&out_id) {
+                Some(&i) => nodes[i].out_wire(),
+                None => source(&Bit::Wire(out_id))?.0,
+            };
+            output.push((out_id, wire));
+        }
+        output.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+        let dirty = vec![false; input.len()];
+        let held = input.iter().map(|_| None).collect();
+        let stale = vec![true; nodes.len()];
 
         Some(Circuit {
+            netlist,
             input,
             output,
-            engine: graph,
+            dirty,
+            stale,
+            nodes,
+            deps,
+            readers,
+            order,
+            levels,
+            input_readers,
+            evaluated: Arc::new(AtomicUsize::new(0)),
+            memories,
+            probes,
+            node_of,
+            buffers,
+            injections: Vec::new(),
+            rng: XorShift(0x2545_f491_4f6c_dd1d),
+            fired: Vec::new(),
+            held,
         })
     }
+    /// 外部入力・cellの出力・メモリの読み出しデータのwireid
+    pub fn wire_ids(&self) -> Vec<WireId> {
+        let mut ids: Vec<WireId> = self.probes.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+    /// 直前の計算でのwireの値。出力と違って読んでも消えない
+    pub fn peek(&self, id: WireId) -> Result<Option<T>, &'static str>
+    where
+        T: Clone,
+    {
+        let wire = self.probes.get(&id).ok_or("selected id is not found")?;
+        let lock = wire.read()?;
+        Ok(lock.as_deref().cloned())
+    }
+    fn memory(&self, name: &str) -> Result<&Arc<MemoryNode<T>>, &'static str> {
+        self.memories
+            .iter()
+            .find(|m| m.spec.is_named(name))
+            .ok_or("selected memory is not found")
+    }
+    pub fn memory_names(&self) -> Vec<&str> {
+        self.memories
+            .iter()
+            .map(|m| m.spec.memid.as_str())
+            .collect()
+    }
+    /// 全メモリのクロック立ち上がり
+    /// 直前の計算結果を使って書き込みと同期読み出しを行う
+    pub fn tick(&mut self) -> Result<(), &'static str>
+    where
+        T: LogicConst,
+    {
+        for mem in self.memories.iter() {
+            mem.tick()?;
+        }
+        self.stale_memory_reads();
+        Ok(())
+    }
+    /// addrはOFFSETを含んだ回路上のアドレス
+    pub fn load_memory(
+        &mut self,
+        name: &str,
+        addr: usize,
+        words: &[Vec<bool>],
+    ) -> Result<(), &'static str> {
+        self.memory(name)?.load(addr, words)?;
+        self.stale_memory_reads();
+        Ok(())
+    }
+    /// メモリの読み出しnodeはcellのnodeとBufNodeの間に並んでいる
+    fn stale_memory_reads(&mut self) {
+        let cells = self.netlist.cells.len();
+        let end = self.nodes.len() - self.buffers.len();
+        self.stale[cells..end].iter_mut().for_each(|s| *s = true);
+    }
+    pub fn dump_memory(&self, name: &str) -> Result<Vec<Vec<bool>>, &'static str> {
+        self.memory(name)?.dump()
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Circuit<T> {
+    /// 呼ぶたびにTaskGraphを組み直すので、入力を変えて何度でも計算できる
+    /// 値の入っていない入力があればspawnする前にErrを返す
+    pub fn culc_async(&mut self) -> Result<BoxFuture<()>, &'static str>
+    where
+        T: LogicOps + LogicConst + Send + Sync + 'static,
+    {
+        let actions = self.prepare()?;
+        self.check_inputs(None)?;
+        self.stale.iter_mut().for_each(|s| *s = false);
+        let engine = self
+            .build_engine(None, |i| {
+                culc_(self.nodes[i].clone(), actions[i], self.evaluated.clone())
+            })
+            .ok_or("failed to build task graph")?;
+        let (_, exec) = engine.execute();
+        Ok(Box::pin(async move {
+            exec.collect::<Vec<_>>().await;
+        }))
+    }
+    /// evaluate_outputsの非同期版
+    pub fn culc_outputs_async(&mut self, ids: &[WireId]) -> Result<BoxFuture<()>, &'static str>
+    where
+        T: LogicOps + LogicConst + Send + Sync + 'static,
+    {
+        let (needed, actions) = self.plan_outputs(ids)?;
+        self.check_inputs(Some(&needed))?;
+        let engine = self
+            .build_engine(Some(&needed), |i| {
+                culc_(self.nodes[i].clone(), actions[i], self.evaluated.clone())
+            })
+            .ok_or("failed to build task graph")?;
+        let (_, exec) = engine.execute();
+        Ok(Box::pin(async move {
+            exec.collect::<Vec<_>>().await;
+        }))
+    }
+    /// ポート名で指定するculc_outputs_async
+    pub fn culc_ports_async(&mut self, names: &[&str]) -> Result<BoxFuture<()>, &'static str>
+    where
+        T: LogicOps + LogicConst + Send + Sync + 'static,
+    {
+        let ids = self.port_outputs(names)?;
+        self.culc_outputs_async(&ids)
+    }
+    /// evaluate_incrementalの非同期版
+    pub fn culc_incremental_async(&mut self) -> Result<BoxFuture<()>, &'static str>
+    where
+        T: LogicOps + LogicConst + PartialEq + Clone + Send + Sync + 'static,
+    {
+        let (needed, force, actions) = self.plan_incremental()?;
+        self.check_inputs(Some(&needed))?;
+        let changed: Arc<Vec<AtomicBool>> = Arc::new(
+            (0..self.nodes.len())
+                .map(|_| AtomicBool::new(false))
+                .collect(),
+        );
+        let engine = self
+            .build_engine(Some(&needed), |i| {
+                culc_incremental_(
+                    self.nodes[i].clone(),
+                    actions[i],
+                    i,
+                    self.deps[i].clone(),
+                    force[i],
+                    changed.clone(),
+                    self.evaluated.clone(),
+                )
+            })
+            .ok_or("failed to build task graph")?;
+        let (_, exec) = engine.execute();
+        Ok(Box::pin(async move {
+            exec.collect::<Vec<_>>().await;
+        }))
+    }
+    /// neededのnode(Noneなら全node)が読む入力に値が入っているか
+    /// taskの中ではErrを返せないので、spawnする前に確かめる
+    fn check_inputs(&mut self, needed: Option<&[bool]>) -> Result<(), &'static str> {
+        for ((_, wire), readers) in self.input.iter().zip(self.input_readers.iter()) {
+            let read = readers.iter().any(|&i| needed.is_none_or(|n| n[i]));
+            if read && wire.get_out().read()?.is_none() {
+                return self.settle(Err("input is not set"));
+            }
+        }
+        Ok(())
+    }
+    /// task(i)をnodes[i]のtaskにする
+    /// neededがあればそこでtrueのnodeだけをTaskGraphに入れる
+    /// TaskGraphにない依存先は計算済みとみなす
+    fn build_engine<F>(&self, needed: Option<&[bool]>, task: F) -> Option<TaskGraph<BoxFuture<()>>>
+    where
+        F: Fn(usize) -> BoxFuture<()>,
+    {
+        let mut graph = TaskGraph::new();
+        let mut index = Vec::with_capacity(self.nodes.len());
+        for i in 0..self.nodes.len() {
+            index.push(match needed {
+                Some(needed) if !needed[i] => None,
+                _ => Some(graph.add_task(task(i)).ok()?),
+            });
+        }
+        for (i, deps) in self.deps.iter().enumerate() {
+            if let Some(task) = &index[i] {
+                for dep in deps.iter().filter_map(|&d| index[d].clone()) {
+                    graph.add_deps(&[dep], task).ok()?;
+                }
+            }
+        }
+        Some(graph)
+    }
+}
+
+fn culc_node<T>(
+    node: &CircuitNode<T>,
+    action: Option<Action>,
+    evaluated: &AtomicUsize,
+) -> Result<(), &'static str>
+where
+    T: LogicOps + LogicConst,
+{
+    node.culc()?;
+    if let Some(action) = action {
+        node.apply(action)?;
+    }
+    evaluated.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+/// forceか依存先のどれかが変わったときだけ計算し、出力が変わればchanged[i]を立てる
+fn culc_incremental_node<T>(
+    node: &CircuitNode<T>,
+    action: Option<Action>,
+    i: usize,
+    deps: &[usize],
+    force: bool,
+    changed: &[AtomicBool],
+    evaluated: &AtomicUsize,
+) -> Result<(), &'static str>
+where
+    T: LogicOps + LogicConst + PartialEq + Clone,
+{
+    if !force && !deps.iter().any(|&d| changed[d].load(Ordering::Acquire)) {
+        return Ok(());
+    }
+    let wire = node.out_wire();
+    let old = wire.read()?.as_deref().cloned();
+    culc_node(node, action, evaluated)?;
+    if wire.read()?.as_deref() != old.as_ref() {
+        changed[i].store(true, Ordering::Release);
+    }
+    Ok(())
+}
+#[cfg(feature = "async")]
+fn culc_<T>(
+    node: Arc<CircuitNode<T>>,
+    action: Option<Action>,
+    evaluated: Arc<AtomicUsize>,
+) -> BoxFuture<()>
+where
+    T: LogicOps + LogicConst + Send + Sync + 'static,
+{
+    Box::pin(async move { culc_node(&node, action, &evaluated).unwrap() })
+}
+#[cfg(feature = "async")]
+fn culc_incremental_<T>(
+    node: Arc<CircuitNode<T>>,
+    action: Option<Action>,
+    i: usize,
+    deps: Vec<usize>,
+    force: bool,
+    changed: Arc<Vec<AtomicBool>>,
+    evaluated: Arc<AtomicUsize>,
+) -> BoxFuture<()>
+where
+    T: LogicOps + LogicConst + PartialEq + Clone + Send + Sync + 'static,
+{
+    Box::pin(async move {
+        culc_incremental_node(&node, action, i, &deps, force, &changed, &evaluated).unwrap()
+    })
 }
 
 pub struct WireIn<T>(Arc<RwLock<Option<Box<T>>>>);
@@ -348,12 +904,16 @@ impl<T> Default for WireIn<T> {
 }
 pub struct WireOut<T>(Arc<RwLock<Option<Box<T>>>>);
 impl<T> WireOut<T> {
+    /// 常にvalを返すwire
+    pub fn constant(val: T) -> Self {
+        WireOut(Arc::new(RwLock::new(Some(Box::new(val)))))
+    }
     /// this method return imediately
-    pub fn try_read(&self) -> Result<RwLockReadGuard<Option<Box<T>>>, &'static str> {
+    pub fn try_read(&self) -> Result<RwLockReadGuard<'_, Option<Box<T>>>, &'static str> {
         self.0.try_read().map_err(|_| "cannot get lock")
     }
     /// this method block thread until get readlock
-    pub fn read(&self) -> Result<RwLockReadGuard<Option<Box<T>>>, &'static str> {
+    pub fn read(&self) -> Result<RwLockReadGuard<'_, Option<Box<T>>>, &'static str> {
         self.0.read().map_err(|_| "lock poisond")
     }
 
@@ -379,17 +939,41 @@ pub enum CircuitNode<T> {
     NxorNode(Option<WireOut<T>>, Option<WireOut<T>>, WireIn<T>),
     XorNode(Option<WireOut<T>>, Option<WireOut<T>>, WireIn<T>),
     NotNode(Option<WireOut<T>>, WireIn<T>),
+    /// 入力をそのまま写す
+    BufNode(Option<WireOut<T>>, WireIn<T>),
+    /// (memory, 読み出しポート番号, bit, out)
+    MemReadNode(Arc<MemoryNode<T>>, usize, usize, WireIn<T>),
 }
 impl<T> CircuitNode<T> {
+    /// 論理ゲートのnodeを作る。inputsはポート名順
+    pub fn gate(type_name: CellType, inputs: Vec<WireOut<T>>, out: WireIn<T>) -> Option<Self> {
+        let mut inputs = inputs.into_iter();
+        let (in0, in1) = (inputs.next(), inputs.next());
+        Some(match type_name {
+            CellType::And => CircuitNode::AndNode(in0, in1, out),
+            CellType::Nand => CircuitNode::NandNode(in0, in1, out),
+            CellType::Or => CircuitNode::OrNode(in0, in1, out),
+            CellType::Nor => CircuitNode::NorNode(in0, in1, out),
+            CellType::Xor => CircuitNode::XorNode(in0, in1, out),
+            CellType::Nxor => CircuitNode::NxorNode(in0, in1, out),
+            CellType::Not => CircuitNode::NotNode(in0, out),
+            CellType::Mem | CellType::MemV2 => return None,
+        })
+    }
     pub fn out_wire(&self) -> WireOut<T> {
+        self.out().get_out()
+    }
+    fn out(&self) -> &WireIn<T> {
         match self {
-            CircuitNode::NandNode(_, _, out) => out.get_out(),
-            CircuitNode::AndNode(_, _, out) => out.get_out(),
-            CircuitNode::NorNode(_, _, out) => out.get_out(),
-            CircuitNode::OrNode(_, _, out) => out.get_out(),
-            CircuitNode::NxorNode(_, _, out) => out.get_out(),
-            CircuitNode::XorNode(_, _, out) => out.get_out(),
-            CircuitNode::NotNode(_, out) => out.get_out(),
+            CircuitNode::NandNode(_, _, out) => out,
+            CircuitNode::AndNode(_, _, out) => out,
+            CircuitNode::NorNode(_, _, out) => out,
+            CircuitNode::OrNode(_, _, out) => out,
+            CircuitNode::NxorNode(_, _, out) => out,
+            CircuitNode::XorNode(_, _, out) => out,
+            CircuitNode::NotNode(_, out) => out,
+            CircuitNode::BufNode(_, out) => out,
+            CircuitNode::MemReadNode(_, _, _, out) => out,
         }
     }
     #[inline]
@@ -402,8 +986,8 @@ impl<T> CircuitNode<T> {
         let res = {
             let rhs_lock = rhs.read()?;
             let lhs_lock = lhs.read()?;
-            let rhs = rhs_lock.as_ref().unwrap();
-            let lhs = lhs_lock.as_ref().unwrap();
+            let rhs = rhs_lock.as_ref().ok_or("input is not set")?;
+            let lhs = lhs_lock.as_ref().ok_or("input is not set")?;
             f(rhs, lhs)
         };
         out.write(Box::new(res))?;
@@ -417,7 +1001,7 @@ impl<T> CircuitNode<T> {
     ) -> Result<(), &'static str> {
         let res = {
             let input_lock = input.read()?;
-            let input = input_lock.as_ref().unwrap();
+            let input = input_lock.as_ref().ok_or("input is not set")?;
             f(input)
         };
         out.write(Box::new(res))?;
@@ -425,7 +1009,7 @@ impl<T> CircuitNode<T> {
     }
     pub fn culc(&self) -> Result<(), &'static str>
     where
-        T: LogicOps,
+        T: LogicOps + LogicConst,
     {
         fn check<T>(x: &Option<WireOut<T>>) -> Result<&WireOut<T>, &'static str> {
             x.as_ref().ok_or("connection less")
@@ -452,18 +1036,399 @@ impl<T> CircuitNode<T> {
             CircuitNode::NotNode(input, out) => {
                 Self::culc_mono_ops_(check(input)?, out, T::lgc_not)
             }
+            CircuitNode::BufNode(input, out) => {
+                Self::culc_mono_ops_(check(input)?, out, T::lgc_buf)
+            }
+            CircuitNode::MemReadNode(mem, port, bit, out) => {
+                let val = mem.read(*port, *bit)?;
+                out.write(Box::new(T::lgc_const(val)))?;
+                Ok(())
+            }
         }
     }
+}
+
+/// (計算し直すかもしれないnode, 必ず計算するnode, 今回の操作)
+type IncrementalPlan = (Vec<bool>, Vec<bool>, Vec<Option<Action>>);
+
+/// 書き込みポートの(en, addr, data)
+type WritePort<T> = (Vec<WireOut<T>>, Vec<WireOut<T>>, Vec<WireOut<T>>);
+
+/// # Sammary
+/// $mem/$mem_v2セル
+/// 中身はbool列で持ち、アドレスとデータはLogicConst::lgc_levelで確定値に直す
+/// # contents
+/// - words: 現在の内容
+/// - latched: 同期読み出しポートがtick()で取り込んだ値
+pub struct MemoryNode<T> {
+    spec: MemoryCell,
+    words: RwLock<Vec<Vec<bool>>>,
+    latched: RwLock<Vec<Vec<bool>>>,
+    read_addr: Vec<Vec<WireOut<T>>>,
+    read_en: Vec<WireOut<T>>,
+    write: Vec<WritePort<T>>,
+}
+impl<T> MemoryNode<T> {
+    fn new<F>(spec: &MemoryCell, source: &F) -> Option<Self>
+    where
+        F: Fn(&Bit) -> Option<(WireOut<T>, Option<usize>)>,
+    {
+        let wires = |bits: &[Bit]| -> Option<Vec<WireOut<T>>> {
+            bits.iter().map(|b| source(b).map(|(w, _)| w)).collect()
+        };
+        let mut read_addr = Vec::new();
+        let mut read_en = Vec::new();
+        for port in spec.read_ports.iter() {
+            read_addr.push(wires(&port.addr)?);
+            read_en.push(source(&port.en)?.0);
+        }
+        let mut write = Vec::new();
+        for port in spec.write_ports.iter() {
+            write.push((wires(&port.en)?, wires(&port.addr)?, wires(&port.data)?));
+        }
+        Some(MemoryNode {
+            words: RwLock::new(spec.init.clone()),
+            latched: RwLock::new(spec.read_ports.iter().map(|p| p.init.clone()).collect()),
+            spec: spec.clone(),
+            read_addr,
+            read_en,
+            write,
+        })
     }
+    fn level(wire: &WireOut<T>) -> Result<bool, &'static str>
+    where
+        T: LogicConst,
+    {
+        let lock = wire.read()?;
+        lock.as_ref()
+            .ok_or("wire is empty")?
+            .lgc_level()
+            .ok_or("memory needs a determined level")
+    }
+    /// wordのindex。範囲外ならNone
+    fn address(&self, addr: &[WireOut<T>]) -> Result<Option<usize>, &'static str>
+    where
+        T: LogicConst,
+    {
+        let mut val = 0usize;
+        for (i, w) in addr.iter().enumerate() {
+            if Self::level(w)? {
+                // usizeに収まらないアドレスは範囲外
+                if i >= usize::BITS as usize {
+                    return Ok(None);
+                }
+                val |= 1 << i;
+            }
+        }
+        Ok(val
+            .checked_sub(self.spec.offset)
+            .filter(|&a| a < self.spec.size))
+    }
+    fn read(&self, port: usize, bit: usize) -> Result<bool, &'static str>
+    where
+        T: LogicConst,
+    {
+        if self.spec.read_ports[port].clocked {
+            let latched = self.latched.read().map_err(|_| "lock poisond")?;
+            return Ok(latched[port][bit]);
+        }
+        // 範囲外の読み出しは0
+        Ok(match self.address(&self.read_addr[port])? {
+            Some(a) => self.words.read().map_err(|_| "lock poisond")?[a][bit],
+            None => false,
+        })
+    }
+    fn tick(&self) -> Result<(), &'static str>
+    where
+        T: LogicConst,
+    {
+        // クロック端の値を先にすべて取り込む
+        let mut reads = Vec::new();
+        for (p, port) in self.spec.read_ports.iter().enumerate() {
+            if port.clocked && Self::level(&self.read_en[p])? {
+                reads.push((p, self.address(&self.read_addr[p])?));
+            }
+        }
+        let mut writes = Vec::new();
+        for (en, addr, data) in self.write.iter() {
+            let mut bits = Vec::new();
+            for (i, e) in en.iter().enumerate() {
+                if Self::level(e)? {
+                    bits.push((i, Self::level(&data[i])?));
+                }
+            }
+            if !bits.is_empty() {
+                writes.push((self.address(addr)?, bits));
+            }
+        }
+
+        let mut words = self.words.write().map_err(|_| "lock poisond")?;
+        let mut latched = self.latched.write().map_err(|_| "lock poisond")?;
+        let mut fetch = |words: &Vec<Vec<bool>>, transparent: bool| {
+            for &(p, a) in reads.iter() {
+                if self.spec.read_ports[p].transparent == transparent {
+                    latched[p] = a.map_or(vec![false; self.spec.width], |a| words[a].clone());
+                }
+            }
+        };
+        fetch(&words, false);
+        // 後のポートほど優先
+        for (a, bits) in writes {
+            if let Some(a) = a {
+                for (i, b) in bits {
+                    words[a][i] = b;
+                }
+            }
+        }
+        fetch(&words, true);
+        Ok(())
+    }
+    fn load(&self, addr: usize, data: &[Vec<bool>]) -> Result<(), &'static str> {
+        let start = addr
+            .checked_sub(self.spec.offset)
+            .ok_or("address out of range")?;
+        if start + data.len() > self.spec.size {
+            return Err("address out of range");
+        }
+        let mut words = self.words.write().map_err(|_| "lock poisond")?;
+        for (i, word) in data.iter().enumerate() {
+            for (j, w) in words[start + i].iter_mut().enumerate() {
+                *w = *word.get(j).unwrap_or(&false);
+            }
+        }
+        Ok(())
+    }
+    fn dump(&self) -> Result<Vec<Vec<bool>>, &'static str> {
+        Ok(self.words.read().map_err(|_| "lock poisond")?.clone())
+    }
+}
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
-        use std::thread::sleep;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
-        async fn from_yosys_test() {
-            
+    fn set_port(circuit: &mut Circuit<bool>, name: &str, val: u64) {
+        let width = circuit.netlist().port(name).unwrap().bits.len();
+        let values = (0..width).map(|i| (val >> i) & 1 == 1).collect();
+        circuit.set_port(name, values).unwrap();
+    }
+    fn get_port(circuit: &mut Circuit<bool>, name: &str) -> u64 {
+        let values = circuit.get_port(name).unwrap();
+        values
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, &b)| acc | ((b as u64) << i))
+    }
 
+    #[test]
+    fn from_yosys_test() {
+        let json = include_str!("yosys_sample.v");
+        let mut circuit = Circuit::<bool>::from_yosys(json).unwrap();
+        // Bを入れないうちは計算できない
+        set_port(&mut circuit, "A", 1);
+        assert_eq!(circuit.evaluate(), Err("input is not set"));
+        for (a, b) in [(0, 0), (3, 5), (7, 9), (15, 15), (8, 8)] {
+            set_port(&mut circuit, "A", a);
+            set_port(&mut circuit, "B", b);
+            circuit.evaluate().unwrap();
+            assert_eq!(get_port(&mut circuit, "X"), (a + b) & 0xf);
+            assert_eq!(get_port(&mut circuit, "carry"), (a + b) >> 4);
         }
+    }
+
+    #[test]
+    fn output_alias_test() {
+        // XがAをそのまま出しても、読み出しでAは消えない
+        let mut netlist = Netlist::from_yosys(include_str!("yosys_sample.v")).unwrap();
+        let a = netlist.port("A").unwrap().bits.clone();
+        let x = netlist.ports.iter_mut().find(|p| p.name == "X").unwrap();
+        x.bits = a;
+        let mut circuit = Circuit::<bool>::from_netlist(netlist).unwrap();
+        set_port(&mut circuit, "A", 0b1010);
+        set_port(&mut circuit, "B", 0);
+        for _ in 0..2 {
+            circuit.evaluate().unwrap();
+            assert_eq!(get_port(&mut circuit, "X"), 0b1010);
+        }
+    }
+
+    #[test]
+    fn lazy_test() {
+        let json = include_str!("../yosys/sample/ripple_res.json");
+        let mut circuit = Circuit::<bool>::from_yosys(json).unwrap();
+        set_port(&mut circuit, "A", 7);
+        set_port(&mut circuit, "B", 9);
+        let x0 = circuit.netlist().port("X").unwrap().bits[0]
+            .wireid()
+            .unwrap();
+        circuit.evaluate_outputs(&[x0]).unwrap();
+        assert!(!*circuit.get_output(x0).unwrap().unwrap());
+        // X[0]のコーンにないcarryは計算されない
+        assert!(circuit.get_port("carry").is_err());
+        circuit.evaluate_ports(&["carry"]).unwrap();
+        assert_eq!(get_port(&mut circuit, "carry"), 1);
+        assert!(circuit.evaluate_ports(&["nothing"]).is_err());
+    }
+
+    #[test]
+    fn incremental_test() {
+        let json = include_str!("../yosys/sample/ripple_res.json");
+        let mut circuit = Circuit::<bool>::from_yosys(json).unwrap();
+        // 出力を読み出すと空になって再計算の対象になるのでpeekで見る
+        let peek = |circuit: &Circuit<bool>, name: &str| -> u64 {
+            let port = circuit.netlist().port(name).unwrap();
+            port.bits.iter().enumerate().fold(0, |acc, (i, b)| {
+                let val = circuit.peek(b.wireid().unwrap()).unwrap().unwrap();
+                acc | ((val as u64) << i)
+            })
+        };
+        set_port(&mut circuit, "A", 3);
+        set_port(&mut circuit, "B", 5);
+        circuit.evaluate_incremental().unwrap();
+        assert_eq!(circuit.evaluated(), circuit.node_count());
+        assert_eq!(peek(&circuit, "X"), 8);
+
+        // 何も変えなければ何も計算しない
+        circuit.evaluate_incremental().unwrap();
+        assert_eq!(circuit.evaluated(), 0);
+
+        // 上位bitだけ変えると下位の桁は計算し直さない
+        set_port(&mut circuit, "A", 11);
+        circuit.evaluate_incremental().unwrap();
+        assert!(0 < circuit.evaluated() && circuit.evaluated() < circuit.node_count());
+        assert_eq!((peek(&circuit, "X"), peek(&circuit, "carry")), (0, 1));
+
+        // 同じ値を書き込んでも直接読むnodeから先へは伝播しない
+        set_port(&mut circuit, "B", 5);
+        circuit.evaluate_incremental().unwrap();
+        let ids = circuit.netlist().port("B").unwrap().bits.clone();
+        let direct: std::collections::HashSet<usize> = circuit
+            .input
+            .iter()
+            .zip(circuit.input_readers.iter())
+            .filter(|((id, _), _)| ids.contains(&Bit::Wire(*id)))
+            .flat_map(|(_, readers)| readers.iter().copied())
+            .collect();
+        assert_eq!(circuit.evaluated(), direct.len());
+        assert_eq!(get_port(&mut circuit, "X"), 0);
+    }
+
+    #[test]
+    fn memory_test() {
+        let json = include_str!("../yosys/sample/ram_res.json");
+        let mut circuit = Circuit::<bool>::from_yosys(json).unwrap();
+        assert_eq!(circuit.memory_names(), vec!["\\mem"]);
+
+        // INITの値が非同期読み出しで見える
+        for addr in 0..4 {
+            set_port(&mut circuit, "raddr", addr);
+            set_port(&mut circuit, "we", 0);
+            set_port(&mut circuit, "waddr", 0);
+            set_port(&mut circuit, "wdata", 0);
+            set_port(&mut circuit, "clk", 0);
+            circuit.evaluate().unwrap();
+            assert_eq!(get_port(&mut circuit, "rdata"), 1 << addr);
+        }
+
+        // 書き込みはtick()で反映される
+        set_port(&mut circuit, "raddr", 2);
+        set_port(&mut circuit, "we", 1);
+        set_port(&mut circuit, "waddr", 2);
+        set_port(&mut circuit, "wdata", 0b0011);
+        set_port(&mut circuit, "clk", 0);
+        circuit.evaluate().unwrap();
+        assert_eq!(get_port(&mut circuit, "rdata"), 0b0100);
+        circuit.tick().unwrap();
+        circuit.evaluate().unwrap();
+        assert_eq!(get_port(&mut circuit, "rdata"), 0b0011);
+        assert_eq!(get_port(&mut circuit, "p"), 0);
+
+        let dump = circuit.dump_memory("mem").unwrap();
+        assert_eq!(dump[2], vec![true, true, false, false]);
+        circuit
+            .load_memory("mem", 0, &[vec![false, true, false, false]])
+            .unwrap();
+        set_port(&mut circuit, "raddr", 0);
+        circuit.evaluate().unwrap();
+        assert_eq!(get_port(&mut circuit, "rdata"), 0b0010);
+        assert_eq!(get_port(&mut circuit, "p"), 1);
+    }
+
+    #[test]
+    fn mem_v2_test() {
+        let json = include_str!("../yosys/sample/ram_res.json")
+            .replace("\"type\": \"$mem\"", "\"type\": \"$mem_v2\"")
+            .replace(
+                "\"RD_CLK_ENABLE\": \"0\",",
+                "\"RD_CLK_ENABLE\": \"1\", \"RD_INIT_VALUE\": \"1010\",",
+            )
+            .replace("\"RD_CLK_POLARITY\": \"0\"", "\"RD_CLK_POLARITY\": \"1\"")
+            .replace("\"RD_CLK\": [ \"x\" ]", "\"RD_CLK\": [ 2 ]");
+        // 同期読み出しは最初のtick()までRD_INIT_VALUEを出す
+        let mut circuit = Circuit::<bool>::from_yosys(&json).unwrap();
+        for port in ["raddr", "we", "waddr", "wdata", "clk"] {
+            set_port(&mut circuit, port, 0);
+        }
+        circuit.evaluate().unwrap();
+        assert_eq!(get_port(&mut circuit, "rdata"), 0b1010);
+
+        // 読み出しポートのリセットは扱えない
+        let json = json.replace(
+            "\"RD_EN\": [ \"x\" ],",
+            "\"RD_EN\": [ \"x\" ], \"RD_SRST\": [ 3 ],",
+        );
+        assert!(Circuit::<bool>::from_yosys(&json).is_none());
+    }
+
+    #[test]
+    fn memory_clock_test() {
+        // tick()一本で表せないクロックは受け付けない
+        let json = include_str!("../yosys/sample/ram_res.json");
+        assert!(Circuit::<bool>::from_yosys(json).is_some());
+        for (from, to) in [
+            ("\"WR_CLK_ENABLE\": \"1\"", "\"WR_CLK_ENABLE\": \"0\""),
+            ("\"WR_CLK_POLARITY\": \"1\"", "\"WR_CLK_POLARITY\": \"0\""),
+            ("\"RD_CLK_ENABLE\": \"0\"", "\"RD_CLK_ENABLE\": \"1\""),
+        ] {
+            let json = json.replace(from, to);
+            assert!(Circuit::<bool>::from_yosys(&json).is_none(), "{}", to);
+        }
+        // 同期読み出しでも、書き込みと別のクロックなら受け付けない
+        let json = json
+            .replace("\"RD_CLK_ENABLE\": \"0\"", "\"RD_CLK_ENABLE\": \"1\"")
+            .replace("\"RD_CLK_POLARITY\": \"0\"", "\"RD_CLK_POLARITY\": \"1\"");
+        assert!(Circuit::<bool>::from_yosys(&json).is_none());
+        let json = json.replace("\"RD_CLK\": [ \"x\" ]", "\"RD_CLK\": [ 2 ]");
+        assert!(Circuit::<bool>::from_yosys(&json).is_some());
+    }
+
+    /// 非同期のエンジンもevaluateと同じ結果になる
+    #[cfg(feature = "async")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn async_engine_test() {
+        let json = include_str!("../yosys/sample/ripple_res.json");
+        let mut sync = Circuit::<bool>::from_yosys(json).unwrap();
+        let mut circuit = Circuit::<bool>::from_yosys(json).unwrap();
+        // 入力が揃うまではspawnせずにErrを返す
+        set_port(&mut circuit, "A", 0);
+        assert_eq!(circuit.culc_async().err(), Some("input is not set"));
+        assert_eq!(
+            circuit.culc_incremental_async().err(),
+            Some("input is not set")
+        );
+        for (a, b) in [(3, 5), (11, 5), (15, 1)] {
+            for c in [&mut sync, &mut circuit] {
+                set_port(c, "A", a);
+                set_port(c, "B", b);
+            }
+            sync.evaluate().unwrap();
+            circuit.culc_async().unwrap().await;
+            assert_eq!(get_port(&mut circuit, "X"), get_port(&mut sync, "X"));
+            circuit.culc_ports_async(&["carry"]).unwrap().await;
+            assert_eq!(get_port(&mut circuit, "carry"), (a + b) >> 4);
+            set_port(&mut circuit, "B", b ^ 1);
+            circuit.culc_incremental_async().unwrap().await;
+            assert_eq!(get_port(&mut circuit, "X"), (a + (b ^ 1)) & 0xf);
+        }
+    }
 }
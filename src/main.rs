@@ -1,8 +1,12 @@
-extern crate tokio;
-
 use std::ops::Not;
 
-use logicproc::{Circuit, CircuitNode, traits::LogicOps};
+use logicproc::{
+    equiv::{equiv, EquivOptions, Equivalence},
+    netlist::Netlist,
+    stats::stats,
+    traits::{LogicConst, LogicOps},
+    Circuit,
+};
 
 #[derive(Clone, Copy, Debug)]
 enum Binary {
@@ -56,17 +60,113 @@ impl LogicOps for Binary {
         (*self).not()
     }
 }
-type BinaryCircuit = CircuitNode<Binary>;
+impl LogicConst for Binary {
+    fn lgc_const(val: bool) -> Self {
+        Binary::from_u32(val as u32)
+    }
 
+    fn lgc_level(&self) -> Option<bool> {
+        Some(self.as_u32() == 1)
+    }
+}
 
+fn load_netlists(paths: &[String]) -> Option<Vec<Netlist>> {
+    let mut netlists = Vec::new();
+    for path in paths.iter() {
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return None;
+            }
+        };
+        match Netlist::from_yosys(&json) {
+            Some(netlist) => netlists.push(netlist),
+            None => {
+                eprintln!("{}: cannot parse yosys json", path);
+                return None;
+            }
+        }
+    }
+    Some(netlists)
+}
 
+/// logicproc stats <a.json>
+fn stats_command(args: &[String]) -> i32 {
+    if args.len() != 1 {
+        eprintln!("usage: logicproc stats <a.json>");
+        return 2;
+    }
+    let netlist = match load_netlists(args) {
+        Some(mut netlists) => netlists.remove(0),
+        None => return 2,
+    };
+    match stats(&netlist) {
+        Ok(stats) => {
+            print!("{}", stats);
+            0
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            2
+        }
+    }
+}
+
+/// logicproc equiv <a.json> <b.json>
+/// 終了コードは等価なら0、等価でなければ1、エラーは2、判定できなければ3
+fn equiv_command(args: &[String]) -> i32 {
+    if args.len() != 2 {
+        eprintln!("usage: logicproc equiv <a.json> <b.json>");
+        return 2;
+    }
+    let netlists = match load_netlists(args) {
+        Some(netlists) => netlists,
+        None => return 2,
+    };
+    match equiv(&netlists[0], &netlists[1], &EquivOptions::default()) {
+        Ok(Equivalence::Equivalent) => {
+            println!("equivalent");
+            0
+        }
+        Ok(Equivalence::Unknown) => {
+            println!("unknown (SAT conflict limit reached)");
+            3
+        }
+        Ok(Equivalence::NotEquivalent(cex)) => {
+            println!("not equivalent: {}", cex.outputs.join(", "));
+            for (name, bits) in cex.inputs.iter() {
+                let value: String = bits
+                    .iter()
+                    .rev()
+                    .map(|&b| if b { '1' } else { '0' })
+                    .collect();
+                println!("  {} = {}'b{}", name, bits.len(), value);
+            }
+            1
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            2
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|s| s.as_str()) {
+        Some("equiv") => std::process::exit(equiv_command(&args[1..])),
+        Some("stats") => std::process::exit(stats_command(&args[1..])),
+        _ => {}
+    }
 
-#[tokio::main]
-async fn main() {
     println!("Hello, world!");
 
     let json = include_str!("yosys_sample.v");
-    let circuit = Circuit::<Binary>::from_yosys(json).unwrap();
+    let mut circuit = Circuit::<Binary>::from_yosys(json).unwrap();
+    for id in circuit.input_ids() {
+        circuit.set_input(id, Box::new(Binary::Zero)).unwrap();
+    }
 
-    let res = tokio::spawn(circuit.culc_async()).await;
+    circuit.evaluate().unwrap();
 }
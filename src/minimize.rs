@@ -0,0 +1,581 @@
+use crate::{
+    netlist::{Bit, Cell, Netlist},
+    opt::{rewire, sweep},
+    slice::fanin_cone,
+    traits::{LogicConst, LogicOps},
+    yosys_parse::{CellType, WireId},
+    Circuit,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+/// # Sammary
+/// 積項。maskの立っている変数だけがリテラルになり、その極性がvalue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cube {
+    pub mask: u32,
+    pub value: u32,
+}
+impl Cube {
+    fn minterm(n: usize, m: u32) -> Self {
+        Cube {
+            mask: full(n),
+            value: m,
+        }
+    }
+    pub fn literals(&self) -> usize {
+        self.mask.count_ones() as usize
+    }
+    pub fn contains(&self, m: u32) -> bool {
+        m & self.mask == self.value
+    }
+    /// 含まれる最小項
+    fn minterms(&self, n: usize) -> impl Iterator<Item = u32> {
+        let free = full(n) & !self.mask;
+        let value = self.value;
+        let mut sub = Some(0u32);
+        std::iter::from_fn(move || {
+            let s = sub?;
+            sub = if s == free {
+                None
+            } else {
+                Some(s.wrapping_sub(free) & free)
+            };
+            Some(value | s)
+        })
+    }
+}
+
+fn full(n: usize) -> u32 {
+    ((1u64 << n) - 1) as u32
+}
+
+/// 積和形の(積項数, リテラル数)
+fn cost(cubes: &[Cube]) -> (usize, usize) {
+    (cubes.len(), cubes.iter().map(|c| c.literals()).sum())
+}
+
+/// # Sammary
+/// Quine–McCluskey法
+/// 主項を全て求め、必須主項のあとは分枝限定で(積項数, リテラル数)が最小の被覆を探す
+/// 探索がnode_limitを超えたらそこまでの最良の被覆を返す
+pub fn quine_mccluskey(n: usize, on: &[bool], node_limit: usize) -> Vec<Cube> {
+    let mut current: HashSet<Cube> = (0..on.len() as u32)
+        .filter(|&m| on[m as usize])
+        .map(|m| Cube::minterm(n, m))
+        .collect();
+    let mut primes: Vec<Cube> = Vec::new();
+    while !current.is_empty() {
+        let mut next = HashSet::new();
+        let mut merged = HashSet::new();
+        for cube in current.iter() {
+            for v in 0..n {
+                let bit = 1 << v;
+                if cube.mask & bit == 0 || cube.value & bit != 0 {
+                    continue;
+                }
+                let other = Cube {
+                    value: cube.value | bit,
+                    ..*cube
+                };
+                if current.contains(&other) {
+                    merged.insert(*cube);
+                    merged.insert(other);
+                    next.insert(Cube {
+                        mask: cube.mask & !bit,
+                        value: cube.value,
+                    });
+                }
+            }
+        }
+        primes.extend(current.iter().filter(|c| !merged.contains(c)));
+        current = next;
+    }
+
+    let minterms: Vec<u32> = (0..on.len() as u32).filter(|&m| on[m as usize]).collect();
+    let covers: Vec<Vec<usize>> = minterms
+        .iter()
+        .map(|&m| {
+            (0..primes.len())
+                .filter(|&p| primes[p].contains(m))
+                .collect()
+        })
+        .collect();
+    let mut chosen = vec![false; primes.len()];
+    for cover in covers.iter() {
+        if cover.len() == 1 {
+            chosen[cover[0]] = true;
+        }
+    }
+    let mut search = Cover {
+        primes: &primes,
+        covers: &covers,
+        best: None,
+        nodes: node_limit,
+    };
+    // まず貪欲に解いて上限にする
+    let mut greedy = chosen.clone();
+    while let Some(cover) = covers
+        .iter()
+        .find(|cover| !cover.iter().any(|&p| greedy[p]))
+    {
+        let &p = cover
+            .iter()
+            .max_by_key(|&&p| {
+                let gain = covers
+                    .iter()
+                    .filter(|c| c.contains(&p) && !c.iter().any(|&q| greedy[q]))
+                    .count();
+                (gain, std::cmp::Reverse(primes[p].literals()))
+            })
+            .unwrap();
+        greedy[p] = true;
+    }
+    search.best = Some(search.pick(&greedy));
+    search.branch(&mut chosen);
+    search.best.unwrap()
+}
+
+struct Cover<'a> {
+    primes: &'a [Cube],
+    /// 最小項毎の、それを含む主項
+    covers: &'a [Vec<usize>],
+    best: Option<Vec<Cube>>,
+    /// 残りの探索node数
+    nodes: usize,
+}
+impl Cover<'_> {
+    fn pick(&self, chosen: &[bool]) -> Vec<Cube> {
+        (0..self.primes.len())
+            .filter(|&p| chosen[p])
+            .map(|p| self.primes[p])
+            .collect()
+    }
+    fn branch(&mut self, chosen: &mut Vec<bool>) {
+        if self.nodes == 0 {
+            return;
+        }
+        self.nodes -= 1;
+        let picked = self.pick(chosen);
+        if self.best.as_ref().is_some_and(|b| cost(&picked) >= cost(b)) {
+            return;
+        }
+        // 含む主項の最も少ない未被覆の最小項で分岐する
+        let uncovered = self
+            .covers
+            .iter()
+            .filter(|cover| !cover.iter().any(|&p| chosen[p]))
+            .min_by_key(|cover| cover.len());
+        let cover = match uncovered {
+            Some(cover) => cover.clone(),
+            None => {
+                self.best = Some(picked);
+                return;
+            }
+        };
+        for p in cover {
+            chosen[p] = true;
+            self.branch(chosen);
+            chosen[p] = false;
+        }
+    }
+}
+
+/// # Sammary
+/// Espresso風の発見的な最小化
+/// 被覆されていない最小項を、OFF集合に触れない範囲でリテラルを一つずつ外して広げ(expand)、
+/// 最後に他の積項だけで被覆できる積項を取り除く(irredundant)
+pub fn espresso(n: usize, on: &[bool]) -> Vec<Cube> {
+    let mut count = vec![0usize; on.len()];
+    let mut cubes: Vec<Cube> = Vec::new();
+    for m in 0..on.len() as u32 {
+        if !on[m as usize] || count[m as usize] > 0 {
+            continue;
+        }
+        let mut cube = Cube::minterm(n, m);
+        for v in 0..n {
+            let wider = Cube {
+                mask: cube.mask & !(1 << v),
+                value: cube.value & !(1 << v),
+            };
+            if wider.minterms(n).all(|x| on[x as usize]) {
+                cube = wider;
+            }
+        }
+        for x in cube.minterms(n) {
+            count[x as usize] += 1;
+        }
+        cubes.push(cube);
+    }
+    // 大きい積項を残したいので、リテラルの多いものから外せるか調べる
+    cubes.sort_by_key(|c| std::cmp::Reverse(c.literals()));
+    let mut kept = Vec::new();
+    for cube in cubes {
+        if cube.minterms(n).all(|x| count[x as usize] > 1) {
+            for x in cube.minterms(n) {
+                count[x as usize] -= 1;
+            }
+        } else {
+            kept.push(cube);
+        }
+    }
+    kept
+}
+
+/// # contents
+/// - max_inputs: これより入力の多いコーンは扱わない
+/// - exact_inputs: これ以下ならQuine–McCluskey、超えればespresso
+/// - node_limit: Quine–McCluskeyの被覆の探索node数の上限
+#[derive(Debug, Clone)]
+pub struct MinimizeOptions {
+    pub max_inputs: usize,
+    pub exact_inputs: usize,
+    pub node_limit: usize,
+}
+impl Default for MinimizeOptions {
+    fn default() -> Self {
+        MinimizeOptions {
+            max_inputs: 16,
+            exact_inputs: 12,
+            node_limit: 100_000,
+        }
+    }
+}
+
+/// 出力のbit毎の結果
+/// リテラル数はコーンにあるNOT以外のゲートの入力ピン数
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputMinimized {
+    pub name: String,
+    pub inputs: usize,
+    pub before: usize,
+    pub after: usize,
+    pub cubes: usize,
+    pub exact: bool,
+    /// 積和形の方が小さく、置き換えたか
+    pub replaced: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MinimizeReport {
+    pub outputs: Vec<OutputMinimized>,
+    /// 入力が多い・メモリを含むなどで扱わなかった出力のbit
+    pub skipped: Vec<String>,
+}
+impl fmt::Display for MinimizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for o in self.outputs.iter() {
+            writeln!(
+                f,
+                "{:<12} inputs {:>2}  literals {:>5} -> {:>5}  cubes {:>4}  {}{}",
+                o.name,
+                o.inputs,
+                o.before,
+                o.after,
+                o.cubes,
+                if o.exact { "qm" } else { "espresso" },
+                if o.replaced { "  replaced" } else { "" }
+            )?;
+        }
+        for name in self.skipped.iter() {
+            writeln!(f, "{:<12} skipped", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// 二入力のANDの鎖で積項を、ORの鎖で和を組んだときのピン数
+fn sop_literals(cubes: &[Cube]) -> usize {
+    let and: usize = cubes
+        .iter()
+        .map(|c| 2 * c.literals().saturating_sub(1))
+        .sum();
+    and + 2 * cubes.len().saturating_sub(1)
+}
+
+struct Builder {
+    next: WireId,
+    cells: Vec<Cell>,
+    /// 入力 -> そのNOTの出力
+    nots: HashMap<WireId, WireId>,
+}
+impl Builder {
+    fn gate(&mut self, prefix: &str, type_name: CellType, inputs: Vec<Bit>) -> Bit {
+        self.next += 1;
+        self.cells.push(Cell {
+            name: format!("{}$sop{}", prefix, self.cells.len()),
+            type_name,
+            inputs,
+            output: self.next,
+        });
+        Bit::Wire(self.next)
+    }
+    fn literal(&mut self, prefix: &str, id: WireId, neg: bool) -> Bit {
+        if !neg {
+            return Bit::Wire(id);
+        }
+        if let Some(&out) = self.nots.get(&id) {
+            return Bit::Wire(out);
+        }
+        let out = self.gate(prefix, CellType::Not, vec![Bit::Wire(id)]);
+        self.nots.insert(id, out.wireid().unwrap());
+        out
+    }
+    fn chain(&mut self, prefix: &str, type_name: CellType, bits: Vec<Bit>, empty: bool) -> Bit {
+        let mut bits = bits.into_iter();
+        let first = match bits.next() {
+            Some(b) => b,
+            None => return Bit::Const(empty),
+        };
+        bits.fold(first, |acc, b| self.gate(prefix, type_name, vec![acc, b]))
+    }
+    /// 積和形の値のbit。inputs[v]が変数v
+    fn sop(&mut self, prefix: &str, cubes: &[Cube], inputs: &[WireId]) -> Bit {
+        let mut products = Vec::new();
+        for cube in cubes.iter() {
+            let lits = (0..inputs.len())
+                .filter(|v| cube.mask >> v & 1 == 1)
+                .map(|v| self.literal(prefix, inputs[v], cube.value >> v & 1 == 0))
+                .collect();
+            products.push(self.chain(prefix, CellType::And, lits, true));
+        }
+        self.chain(prefix, CellType::Or, products, false)
+    }
+}
+
+/// # Sammary
+/// 入力の少ない出力のbit毎に、コーンを全入力パターンでシミュレーションして真理値表を作り、
+/// 積和形に最小化する。リテラル数が減るものはコーンを積和形の二段の回路に置き換え、
+/// 使われなくなったcellはsweepで取り除く
+pub fn minimize(
+    netlist: &Netlist,
+    options: &MinimizeOptions,
+) -> Result<(Netlist, MinimizeReport), &'static str> {
+    let mut circuit =
+        Circuit::<u64>::from_netlist(netlist.clone()).ok_or("cannot build circuit")?;
+    let mut report = MinimizeReport::default();
+    let mut builder = Builder {
+        next: netlist.max_wireid(),
+        cells: Vec::new(),
+        nots: HashMap::new(),
+    };
+    let mut replaced: Vec<WireId> = Vec::new();
+    let mut subst: HashMap<WireId, Bit> = HashMap::new();
+    let mut seen: HashSet<WireId> = HashSet::new();
+    for port in netlist.output_ports() {
+        for (i, bit) in port.bits.iter().enumerate() {
+            let name = port.bit_name(i);
+            let out = match bit.wireid() {
+                Some(id) if seen.insert(id) => id,
+                _ => continue,
+            };
+            let cone = fanin_cone(netlist, &[name.as_str()])?;
+            let n = cone.inputs.len();
+            if !cone.memories.is_empty() || n > options.max_inputs {
+                report.skipped.push(name);
+                continue;
+            }
+            if cone.cells.is_empty() {
+                continue;
+            }
+
+            let mut on = vec![false; 1 << n];
+            for base in (0..on.len()).step_by(64) {
+                let lanes = (on.len() - base).min(64);
+                for (v, &id) in cone.inputs.iter().enumerate() {
+                    let val =
+                        (0..lanes).fold(0u64, |acc, j| acc | (((base + j) >> v & 1) as u64) << j);
+                    circuit.set_input(id, Box::new(val))?;
+                }
+                circuit.evaluate_outputs(&[out])?;
+                let val = circuit.peek(out)?.ok_or("output is empty")?;
+                for j in 0..lanes {
+                    on[base + j] = val >> j & 1 == 1;
+                }
+            }
+
+            let exact = n <= options.exact_inputs;
+            let cubes = if exact {
+                quine_mccluskey(n, &on, options.node_limit)
+            } else {
+                espresso(n, &on)
+            };
+            let before: usize = cone
+                .cells
+                .iter()
+                .map(|&c| &netlist.cells[c])
+                .filter(|c| c.type_name != CellType::Not)
+                .map(|c| c.inputs.len())
+                .sum();
+            let after = sop_literals(&cubes);
+            let replace = after < before;
+            if replace {
+                let start = builder.cells.len();
+                match builder.sop(&name, &cubes, &cone.inputs) {
+                    // 最後に作ったゲートに元の出力のwireを引き継がせる
+                    Bit::Wire(id) if builder.cells.len() > start && id == builder.next => {
+                        builder.cells.last_mut().unwrap().output = out;
+                        for not in builder.nots.values_mut().filter(|w| **w == id) {
+                            *not = out;
+                        }
+                    }
+                    bit => {
+                        subst.insert(out, bit);
+                    }
+                }
+                replaced.push(out);
+            }
+            report.outputs.push(OutputMinimized {
+                name,
+                inputs: n,
+                before,
+                after,
+                cubes: cubes.len(),
+                exact,
+                replaced: replace,
+            });
+        }
+    }
+
+    let mut cells: Vec<Cell> = netlist
+        .cells
+        .iter()
+        .filter(|c| !replaced.contains(&c.output))
+        .cloned()
+        .collect();
+    cells.extend(builder.cells);
+    let (minimized, _) = sweep(&rewire(netlist, cells, &subst));
+    Ok((minimized, report))
+}
+
+impl<T> Circuit<T> {
+    /// minimizeしたnetlistから作り直した回路
+    pub fn minimized(
+        &self,
+        options: &MinimizeOptions,
+    ) -> Result<(Circuit<T>, MinimizeReport), &'static str>
+    where
+        T: LogicOps + LogicConst,
+    {
+        let (netlist, report) = minimize(self.netlist(), options)?;
+        let circuit = Circuit::from_netlist(netlist).ok_or("cannot build circuit")?;
+        Ok((circuit, report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        equiv::{equiv, EquivOptions, Equivalence},
+        netlist::Port,
+        util::XorShift,
+        yosys_parse::Direction,
+    };
+
+    #[test]
+    fn two_level() {
+        let mut rng = XorShift(3);
+        for n in [3, 5, 8] {
+            let on: Vec<bool> = (0..1 << n).map(|_| rng.next().is_multiple_of(3)).collect();
+            let exact = quine_mccluskey(n, &on, 100_000);
+            let heuristic = espresso(n, &on);
+            for cubes in [&exact, &heuristic] {
+                for m in 0..on.len() as u32 {
+                    assert_eq!(cubes.iter().any(|c| c.contains(m)), on[m as usize]);
+                }
+            }
+            assert!(cost(&exact) <= cost(&heuristic));
+        }
+        // ab + a!b = a
+        let on = [false, true, false, true];
+        assert_eq!(
+            quine_mccluskey(2, &on, 100),
+            vec![Cube { mask: 1, value: 1 }]
+        );
+    }
+
+    #[test]
+    fn replace_cones() {
+        let mut netlist =
+            Netlist::from_yosys(include_str!("../yosys/sample/ripple_res.json")).unwrap();
+        // y = (a0 & b0) | (a0 & !b0) は a0
+        let a0 = netlist.port("A").unwrap().bits[0];
+        let b0 = netlist.port("B").unwrap().bits[0];
+        let next = netlist.max_wireid();
+        for (i, (type_name, inputs)) in [
+            (CellType::Not, vec![b0]),
+            (CellType::And, vec![a0, b0]),
+            (CellType::And, vec![a0, Bit::Wire(next + 1)]),
+            (CellType::Or, vec![Bit::Wire(next + 2), Bit::Wire(next + 3)]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            netlist.cells.push(Cell {
+                name: format!("redundant{}", i),
+                type_name,
+                inputs,
+                output: next + 1 + i as WireId,
+            });
+        }
+        netlist.ports.push(Port {
+            name: "Y".to_string(),
+            direction: Direction::Out,
+            bits: vec![Bit::Wire(next + 4)],
+        });
+
+        let (minimized, report) = minimize(&netlist, &MinimizeOptions::default()).unwrap();
+        let y = report.outputs.iter().find(|o| o.name == "Y").unwrap();
+        assert_eq!((y.before, y.after, y.replaced), (6, 0, true));
+        assert_eq!(minimized.port("Y").unwrap().bits, vec![a0]);
+        assert!(report.outputs.iter().any(|o| o.name == "carry"));
+        assert_eq!(
+            equiv(&netlist, &minimized, &EquivOptions::default()),
+            Ok(Equivalence::Equivalent)
+        );
+    }
+
+    #[test]
+    fn replaced_output_feeds_logic() {
+        let mut netlist =
+            Netlist::from_yosys(include_str!("../yosys/sample/ripple_res.json")).unwrap();
+        // y = a0 & (a0 | b0) は a0 になり、z = y ^ b0 はそれを読み続ける
+        let a0 = netlist.port("A").unwrap().bits[0];
+        let b0 = netlist.port("B").unwrap().bits[0];
+        let next = netlist.max_wireid();
+        for (i, (type_name, inputs)) in [
+            (CellType::Or, vec![a0, b0]),
+            (CellType::And, vec![a0, Bit::Wire(next + 1)]),
+            (CellType::Xor, vec![Bit::Wire(next + 2), b0]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            netlist.cells.push(Cell {
+                name: format!("redundant{}", i),
+                type_name,
+                inputs,
+                output: next + 1 + i as WireId,
+            });
+        }
+        for (name, id) in [("Y", next + 2), ("Z", next + 3)] {
+            netlist.ports.push(Port {
+                name: name.to_string(),
+                direction: Direction::Out,
+                bits: vec![Bit::Wire(id)],
+            });
+        }
+
+        let (minimized, report) = minimize(&netlist, &MinimizeOptions::default()).unwrap();
+        assert!(report.outputs.iter().any(|o| o.name == "Y" && o.replaced));
+        assert_eq!(minimized.port("Y").unwrap().bits, vec![a0]);
+        assert!(Circuit::<bool>::from_netlist(minimized.clone()).is_some());
+        assert_eq!(
+            equiv(&netlist, &minimized, &EquivOptions::default()),
+            Ok(Equivalence::Equivalent)
+        );
+    }
+}
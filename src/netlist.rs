@@ -0,0 +1,499 @@
+use crate::{
+    traits::LogicOps,
+    yosys_parse::{BitElem, CellElem, CellType, Direction, ModuleElem, WireId, YosysRootElem},
+};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// ポート名とその値(LSB first)の組
+pub type PortValues = Vec<(String, Vec<bool>)>;
+
+/// # Sammary
+/// yosysのモジュールを回路構築・解析しやすい形に整理したもの
+/// # contents
+/// - ports: モジュールのポート(名前順)
+/// - cells: 論理ゲート
+/// - memories: $mem/$mem_v2セル
+/// - netnames: ネット名とそのbit
+#[derive(Clone)]
+pub struct Netlist {
+    pub name: String,
+    pub ports: Vec<Port>,
+    pub cells: Vec<Cell>,
+    pub memories: Vec<MemoryCell>,
+    pub netnames: Vec<(String, Vec<Bit>)>,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash, PartialOrd, Ord)]
+pub enum Bit {
+    Wire(WireId),
+    Const(bool),
+}
+impl Bit {
+    pub fn wireid(&self) -> Option<WireId> {
+        match self {
+            Bit::Wire(id) => Some(*id),
+            Bit::Const(_) => None,
+        }
+    }
+}
+impl From<BitElem> for Bit {
+    fn from(b: BitElem) -> Self {
+        match b {
+            BitElem::Wire(id) => Bit::Wire(id),
+            BitElem::Const(c) => Bit::Const(c.as_bool()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Port {
+    pub name: String,
+    pub direction: Direction,
+    pub bits: Vec<Bit>,
+}
+
+#[derive(Clone)]
+pub struct Cell {
+    pub name: String,
+    pub type_name: CellType,
+    pub inputs: Vec<Bit>,
+    pub output: WireId,
+}
+
+#[derive(Clone)]
+pub struct MemoryCell {
+    pub name: String,
+    pub memid: String,
+    pub width: usize,
+    pub size: usize,
+    pub offset: usize,
+    /// word毎のLSB firstのbit列
+    pub init: Vec<Vec<bool>>,
+    pub read_ports: Vec<MemReadPort>,
+    pub write_ports: Vec<MemWritePort>,
+}
+
+#[derive(Clone)]
+pub struct MemReadPort {
+    /// falseなら非同期読み出し(enは無視される)
+    pub clocked: bool,
+    pub transparent: bool,
+    pub en: Bit,
+    pub addr: Vec<Bit>,
+    pub data: Vec<WireId>,
+    /// 同期読み出しで最初のtick()までに出す値($mem_v2のRD_INIT_VALUE)
+    pub init: Vec<bool>,
+}
+
+#[derive(Clone)]
+pub struct MemWritePort {
+    pub en: Vec<Bit>,
+    pub addr: Vec<Bit>,
+    pub data: Vec<Bit>,
+}
+
+impl Port {
+    /// i番目のbitの"ポート名[i]"(1bitのポートは"ポート名")
+    pub fn bit_name(&self, i: usize) -> String {
+        if self.bits.len() == 1 {
+            self.name.clone()
+        } else {
+            format!("{}[{}]", self.name, i)
+        }
+    }
+}
+
+impl Cell {
+    /// 入力値(ポート名順)からゲートの出力を求める
+    pub fn eval<T: LogicOps>(&self, ins: &[T]) -> Option<T> {
+        let a = ins.first()?;
+        Some(match self.type_name {
+            CellType::And => a.lgc_and(ins.get(1)?),
+            CellType::Nand => a.lgc_nand(ins.get(1)?),
+            CellType::Or => a.lgc_or(ins.get(1)?),
+            CellType::Nor => a.lgc_nor(ins.get(1)?),
+            CellType::Xor => a.lgc_xor(ins.get(1)?),
+            CellType::Nxor => a.lgc_nxor(ins.get(1)?),
+            CellType::Not => a.lgc_not(),
+            CellType::Mem | CellType::MemV2 => return None,
+        })
+    }
+}
+
+impl Netlist {
+    pub fn from_yosys(json: &str) -> Option<Self> {
+        let yosys = YosysRootElem::from_json(json)?;
+        let (name, module) = yosys.modules.iter().last()?;
+        Self::from_module(name, module)
+    }
+    pub fn from_module(name: &str, module: &ModuleElem) -> Option<Self> {
+        let mut ports: Vec<Port> = module
+            .ports
+            .iter()
+            .map(|(name, port)| Port {
+                name: name.clone(),
+                direction: port.direction,
+                bits: port.bits.iter().map(|&b| b.into()).collect(),
+            })
+            .collect();
+        ports.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut cell_names: Vec<&String> = module.cells.keys().collect();
+        cell_names.sort();
+        let mut cells = Vec::new();
+        let mut memories = Vec::new();
+        for name in cell_names {
+            let cell = &module.cells[name];
+            match cell.type_name {
+                CellType::Mem | CellType::MemV2 => {
+                    memories.push(MemoryCell::from_cell(name, cell)?);
+                }
+                _ => cells.push(Cell {
+                    name: name.clone(),
+                    type_name: cell.type_name,
+                    inputs: cell.input_bits().into_iter().map(|b| b.into()).collect(),
+                    output: cell.output_wireid(),
+                }),
+            }
+        }
+
+        let mut netnames: Vec<(String, Vec<Bit>)> = module
+            .netnames
+            .iter()
+            .map(|(name, net)| (name.clone(), net.bits.iter().map(|&b| b.into()).collect()))
+            .collect();
+        netnames.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Some(Netlist {
+            name: name.to_string(),
+            ports,
+            cells,
+            memories,
+            netnames,
+        })
+    }
+    pub fn port(&self, name: &str) -> Option<&Port> {
+        self.ports.iter().find(|p| p.name == name)
+    }
+    pub fn input_ports(&self) -> impl Iterator<Item = &Port> {
+        self.ports.iter().filter(|p| p.direction == Direction::In)
+    }
+    pub fn output_ports(&self) -> impl Iterator<Item = &Port> {
+        self.ports.iter().filter(|p| p.direction == Direction::Out)
+    }
+    /// 入力ポートのwireid(昇順)
+    pub fn input_ids(&self) -> Vec<WireId> {
+        let mut ids: Vec<WireId> = self
+            .input_ports()
+            .flat_map(|p| p.bits.iter().filter_map(|b| b.wireid()))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+    /// 出力ポートのwireid(昇順)
+    pub fn output_ids(&self) -> Vec<WireId> {
+        let mut ids: Vec<WireId> = self
+            .output_ports()
+            .flat_map(|p| p.bits.iter().filter_map(|b| b.wireid()))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+    /// 使われている最大のwireid
+    pub fn max_wireid(&self) -> WireId {
+        let mut bits: Vec<Bit> = Vec::new();
+        for port in self.ports.iter() {
+            bits.extend(port.bits.iter());
+        }
+        for cell in self.cells.iter() {
+            bits.extend(cell.inputs.iter());
+            bits.push(Bit::Wire(cell.output));
+        }
+        for mem in self.memories.iter() {
+            for port in mem.read_ports.iter() {
+                bits.extend(port.addr.iter());
+                bits.push(port.en);
+                bits.extend(port.data.iter().map(|&id| Bit::Wire(id)));
+            }
+            for port in mem.write_ports.iter() {
+                bits.extend(
+                    port.en
+                        .iter()
+                        .chain(port.addr.iter())
+                        .chain(port.data.iter()),
+                );
+            }
+        }
+        bits.iter().filter_map(|b| b.wireid()).max().unwrap_or(1)
+    }
+    /// wireid -> それを出力するcellのindex
+    pub fn drivers(&self) -> HashMap<WireId, usize> {
+        self.cells
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (c.output, i))
+            .collect()
+    }
+    /// cellのindexを入力側から順に並べたもの
+    /// メモリの読み出しデータは外部入力と同じく扱う
+    pub fn topo_order(&self) -> Result<Vec<usize>, &'static str> {
+        let drivers = self.drivers();
+        let mut fanouts: Vec<Vec<usize>> = vec![Vec::new(); self.cells.len()];
+        let mut count = vec![0usize; self.cells.len()];
+        for (i, cell) in self.cells.iter().enumerate() {
+            for bit in cell.inputs.iter() {
+                if let Some(&d) = bit.wireid().and_then(|id| drivers.get(&id)) {
+                    fanouts[d].push(i);
+                    count[i] += 1;
+                }
+            }
+        }
+        let mut order: Vec<usize> = (0..self.cells.len()).filter(|&i| count[i] == 0).collect();
+        let mut head = 0;
+        while head < order.len() {
+            let i = order[head];
+            head += 1;
+            for &j in fanouts[i].iter() {
+                count[j] -= 1;
+                if count[j] == 0 {
+                    order.push(j);
+                }
+            }
+        }
+        if order.len() != self.cells.len() {
+            return Err("combinational loop");
+        }
+        Ok(order)
+    }
+    /// "ネット名[bit]"(1bitのネットは"ネット名")に対応するwireid
+    pub fn find_wire(&self, name: &str) -> Option<WireId> {
+        let (net, index) = match name.strip_suffix(']').and_then(|n| n.rsplit_once('[')) {
+            Some((net, i)) => (net, Some(i.parse::<usize>().ok()?)),
+            None => (name, None),
+        };
+        let (_, bits) = self.netnames.iter().find(|(n, _)| n == net)?;
+        let bit = match index {
+            Some(i) => bits.get(i)?,
+            None if bits.len() == 1 => &bits[0],
+            None => return None,
+        };
+        bit.wireid()
+    }
+    /// # Sammary
+    /// yosysのJSONに書き出す
+    /// ゲートの入力はA,B、出力はYのポートにつなぐ。メモリには対応しない
+    pub fn to_yosys(&self) -> Result<String, &'static str> {
+        if !self.memories.is_empty() {
+            return Err("memory is not supported");
+        }
+        let bits = |bits: &[Bit]| -> Vec<Value> {
+            bits.iter()
+                .map(|b| match b {
+                    Bit::Wire(id) => json!(id),
+                    Bit::Const(c) => json!(if *c { "1" } else { "0" }),
+                })
+                .collect()
+        };
+        let direction = |d: Direction| match d {
+            Direction::In => "input",
+            Direction::Out => "output",
+            Direction::InOut => "inout",
+        };
+        let mut ports = Map::new();
+        for port in self.ports.iter() {
+            ports.insert(
+                port.name.clone(),
+                json!({ "direction": direction(port.direction), "bits": bits(&port.bits) }),
+            );
+        }
+        let mut cells = Map::new();
+        for cell in self.cells.iter() {
+            let mut directions = Map::new();
+            let mut connections = Map::new();
+            for (i, bit) in cell.inputs.iter().enumerate() {
+                let pin = ((b'A' + i as u8) as char).to_string();
+                directions.insert(pin.clone(), json!("input"));
+                connections.insert(pin, json!(bits(&[*bit])));
+            }
+            directions.insert("Y".to_string(), json!("output"));
+            connections.insert("Y".to_string(), json!([cell.output]));
+            cells.insert(
+                cell.name.clone(),
+                json!({
+                    "hide_name": cell.name.starts_with('$') as i32,
+                    "type": cell.type_name.name(),
+                    "parameters": {},
+                    "attributes": {},
+                    "port_directions": directions,
+                    "connections": connections,
+                }),
+            );
+        }
+        let mut netnames = Map::new();
+        for (name, net) in self.netnames.iter() {
+            netnames.insert(
+                name.clone(),
+                json!({ "hide_name": name.starts_with('$') as i32, "bits": bits(net), "attributes": {} }),
+            );
+        }
+        let root = json!({
+            "creator": "logicproc",
+            "modules": {
+                &self.name: {
+                    "attributes": {},
+                    "ports": ports,
+                    "cells": cells,
+                    "netnames": netnames,
+                }
+            }
+        });
+        serde_json::to_string_pretty(&root).map_err(|_| "cannot serialize")
+    }
+    /// 表示用の名前。ネット名がなければ"wire<id>"
+    pub fn wire_label(&self, id: WireId) -> String {
+        self.wire_name(id).unwrap_or_else(|| format!("wire{}", id))
+    }
+    /// wireidに対応する"ネット名[bit]"
+    /// 同じbitに複数の名前があるときは隠し名でないものを優先する
+    pub fn wire_name(&self, id: WireId) -> Option<String> {
+        let mut found: Option<String> = None;
+        for (name, bits) in self.netnames.iter() {
+            if let Some(i) = bits.iter().position(|&b| b == Bit::Wire(id)) {
+                let full = if bits.len() == 1 {
+                    name.clone()
+                } else {
+                    format!("{}[{}]", name, i)
+                };
+                if !name.starts_with('$') {
+                    return Some(full);
+                }
+                found.get_or_insert(full);
+            }
+        }
+        found
+    }
+}
+
+impl MemoryCell {
+    fn from_cell(name: &str, cell: &CellElem) -> Option<Self> {
+        let width = cell.param_u64("WIDTH")? as usize;
+        let size = cell.param_u64("SIZE")? as usize;
+        let abits = cell.param_u64("ABITS")? as usize;
+        let offset = cell.param_u64("OFFSET").unwrap_or(0) as usize;
+        let memid = cell.param_str("MEMID").unwrap_or(name).to_string();
+
+        let init_bits = cell.param_bits("INIT").unwrap_or_default();
+        let init = (0..size)
+            .map(|i| {
+                (0..width)
+                    .map(|j| *init_bits.get(i * width + j).unwrap_or(&false))
+                    .collect()
+            })
+            .collect();
+
+        let bits = |port: &str| -> Vec<Bit> {
+            cell.connection(port)
+                .map(|v| v.iter().map(|&b| b.into()).collect())
+                .unwrap_or_default()
+        };
+        let flag_or = |param: &str, i: usize, default: bool| -> bool {
+            cell.param_bits(param)
+                .and_then(|b| b.get(i).copied())
+                .unwrap_or(default)
+        };
+        let flag = |param: &str, i: usize| flag_or(param, i, false);
+
+        let rd_ports = cell.param_u64("RD_PORTS")? as usize;
+        let rd_en = bits("RD_EN");
+        let rd_addr = bits("RD_ADDR");
+        let rd_data = bits("RD_DATA");
+        let wr_ports = cell.param_u64("WR_PORTS")? as usize;
+        // $mem_v2は(読み出しポート x 書き込みポート)毎のマスク
+        let transparent = |i: usize| -> bool {
+            match cell.type_name {
+                CellType::MemV2 => {
+                    (0..wr_ports).any(|j| flag("RD_TRANSPARENCY_MASK", i * wr_ports + j))
+                }
+                _ => flag("RD_TRANSPARENT", i),
+            }
+        };
+        // 読み出しポートのリセットは扱えないので、つながっていれば受け付けない
+        let rd_arst = bits("RD_ARST");
+        let rd_srst = bits("RD_SRST");
+        let rd_init = cell.param_bits("RD_INIT_VALUE").unwrap_or_default();
+        let mut read_ports = Vec::new();
+        for i in 0..rd_ports {
+            for reset in [&rd_arst, &rd_srst] {
+                if !matches!(reset.get(i), None | Some(Bit::Const(false))) {
+                    return None;
+                }
+            }
+            read_ports.push(MemReadPort {
+                clocked: flag("RD_CLK_ENABLE", i),
+                transparent: transparent(i),
+                en: *rd_en.get(i).unwrap_or(&Bit::Const(true)),
+                addr: rd_addr.get(i * abits..(i + 1) * abits)?.to_vec(),
+                data: rd_data
+                    .get(i * width..(i + 1) * width)?
+                    .iter()
+                    .map(|b| b.wireid())
+                    .collect::<Option<Vec<_>>>()?,
+                init: (0..width)
+                    .map(|j| *rd_init.get(i * width + j).unwrap_or(&false))
+                    .collect(),
+            });
+        }
+
+        // tick()は全メモリ共通の立ち上がりなので、同期ポートが一本の正論理のクロックを
+        // 共有していて、書き込みがすべて同期のときだけ受け付ける
+        let rd_clk = bits("RD_CLK");
+        let wr_clk = bits("WR_CLK");
+        let mut clocks = Vec::new();
+        for i in 0..rd_ports {
+            if flag("RD_CLK_ENABLE", i) {
+                if !flag_or("RD_CLK_POLARITY", i, true) {
+                    return None;
+                }
+                clocks.push(*rd_clk.get(i)?);
+            }
+        }
+        for i in 0..wr_ports {
+            if !flag_or("WR_CLK_ENABLE", i, true) || !flag_or("WR_CLK_POLARITY", i, true) {
+                return None;
+            }
+            clocks.push(*wr_clk.get(i)?);
+        }
+        if clocks.windows(2).any(|w| w[0] != w[1]) {
+            return None;
+        }
+
+        let wr_en = bits("WR_EN");
+        let wr_addr = bits("WR_ADDR");
+        let wr_data = bits("WR_DATA");
+        let mut write_ports = Vec::new();
+        for i in 0..wr_ports {
+            write_ports.push(MemWritePort {
+                en: wr_en.get(i * width..(i + 1) * width)?.to_vec(),
+                addr: wr_addr.get(i * abits..(i + 1) * abits)?.to_vec(),
+                data: wr_data.get(i * width..(i + 1) * width)?.to_vec(),
+            });
+        }
+
+        Some(MemoryCell {
+            name: name.to_string(),
+            memid,
+            width,
+            size,
+            offset,
+            init,
+            read_ports,
+            write_ports,
+        })
+    }
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name == name || self.memid == name || self.memid.trim_start_matches('\\') == name
+    }
+}
@@ -0,0 +1,494 @@
+use crate::{
+    netlist::{Bit, Cell, Netlist},
+    traits::{LogicConst, LogicOps},
+    yosys_parse::{CellType, WireId},
+    Circuit,
+};
+use std::{collections::HashMap, fmt};
+
+/// # contents
+/// - constants: 定数に畳み込んだcell
+/// - buffers: 入力の一つをそのまま出すだけになったcell(冪等・吸収を含む)
+/// - inverters: 二重のNOTを打ち消したcell
+/// - rewritten: NOTに書き換えたcell
+/// - merged: 同じ種類・同じ入力のcellにまとめたcell
+/// - swept: 出力にもメモリにも届かないので取り除いたcellの名前
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OptReport {
+    pub before: usize,
+    pub after: usize,
+    pub constants: usize,
+    pub buffers: usize,
+    pub inverters: usize,
+    pub rewritten: usize,
+    pub merged: usize,
+    pub swept: Vec<String>,
+}
+impl OptReport {
+    pub fn removed(&self) -> usize {
+        self.before - self.after
+    }
+    /// selfのあとにnextのpassをかけた結果としてまとめる
+    fn then(mut self, next: OptReport) -> Self {
+        self.after = next.after;
+        self.constants += next.constants;
+        self.buffers += next.buffers;
+        self.inverters += next.inverters;
+        self.rewritten += next.rewritten;
+        self.merged += next.merged;
+        self.swept.extend(next.swept);
+        self
+    }
+}
+impl fmt::Display for OptReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "cells: {} -> {} (removed {})",
+            self.before,
+            self.after,
+            self.removed()
+        )?;
+        writeln!(f, "  constant   {}", self.constants)?;
+        writeln!(f, "  buffer     {}", self.buffers)?;
+        writeln!(f, "  double not {}", self.inverters)?;
+        writeln!(f, "  to not     {}", self.rewritten)?;
+        writeln!(f, "  merged     {}", self.merged)?;
+        writeln!(f, "  swept      {}", self.swept.len())?;
+        for name in self.swept.iter() {
+            writeln!(f, "    {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// cellを簡単化した結果
+enum Reduced {
+    Const(bool),
+    Bit(Bit),
+    Not(Bit),
+    Keep,
+}
+
+/// 残したcellの出力 -> (種類, 入力)
+type Defs = HashMap<WireId, (CellType, Vec<Bit>)>;
+
+/// bがNOT xの出力ならx
+fn inverse(defs: &Defs, b: Bit) -> Option<Bit> {
+    match defs.get(&b.wireid()?)? {
+        (CellType::Not, ins) => Some(ins[0]),
+        _ => None,
+    }
+}
+
+/// bが種類type_nameでaを入力に持つcellの出力か
+fn has_input(defs: &Defs, b: Bit, type_name: CellType, a: Bit) -> bool {
+    b.wireid()
+        .and_then(|id| defs.get(&id))
+        .is_some_and(|(t, ins)| *t == type_name && ins.contains(&a))
+}
+
+fn reduce(type_name: CellType, ins: &[Bit], defs: &Defs) -> Reduced {
+    use Bit::Const;
+    use CellType::*;
+    // 出力の反転を外してAND/OR/XORとして考える
+    let (base, neg) = match type_name {
+        Not => {
+            return match ins[0] {
+                Const(c) => Reduced::Const(!c),
+                a => Reduced::Not(a),
+            }
+        }
+        And => (And, false),
+        Nand => (And, true),
+        Or => (Or, false),
+        Nor => (Or, true),
+        Xor => (Xor, false),
+        Nxor => (Xor, true),
+        Mem | MemV2 => return Reduced::Keep,
+    };
+    let (a, b) = (ins[0], ins[1]);
+    let complement = inverse(defs, a) == Some(b) || inverse(defs, b) == Some(a);
+    let reduced = match (base, a, b) {
+        (And, Const(x), Const(y)) => Reduced::Const(x && y),
+        (Or, Const(x), Const(y)) => Reduced::Const(x || y),
+        (_, Const(x), Const(y)) => Reduced::Const(x != y),
+        (And, Const(false), _) | (And, _, Const(false)) => Reduced::Const(false),
+        (And, Const(true), x) | (And, x, Const(true)) => Reduced::Bit(x),
+        (Or, Const(true), _) | (Or, _, Const(true)) => Reduced::Const(true),
+        (Or, Const(false), x) | (Or, x, Const(false)) => Reduced::Bit(x),
+        (Xor, Const(c), x) | (Xor, x, Const(c)) if c => Reduced::Not(x),
+        (Xor, Const(_), x) | (Xor, x, Const(_)) => Reduced::Bit(x),
+        (And | Or, a, b) if a == b => Reduced::Bit(a),
+        (Xor, a, b) if a == b => Reduced::Const(false),
+        (And, ..) if complement => Reduced::Const(false),
+        (Or, ..) | (Xor, ..) if complement => Reduced::Const(true),
+        // 吸収: a & (a | x) = a, a | (a & x) = a
+        (And, a, b) if has_input(defs, b, Or, a) => Reduced::Bit(a),
+        (And, a, b) if has_input(defs, a, Or, b) => Reduced::Bit(b),
+        (Or, a, b) if has_input(defs, b, And, a) => Reduced::Bit(a),
+        (Or, a, b) if has_input(defs, a, And, b) => Reduced::Bit(b),
+        // 冪等: a & (a & x) = a & x
+        (And | Or, a, b) if has_input(defs, b, base, a) => Reduced::Bit(b),
+        (And | Or, a, b) if has_input(defs, a, base, b) => Reduced::Bit(a),
+        _ => return Reduced::Keep,
+    };
+    match (reduced, neg) {
+        (r, false) => r,
+        (Reduced::Const(c), true) => Reduced::Const(!c),
+        (Reduced::Bit(x), true) => Reduced::Not(x),
+        (Reduced::Not(x), true) => Reduced::Bit(x),
+        (Reduced::Keep, true) => Reduced::Keep,
+    }
+}
+
+/// # Sammary
+/// 定数の畳み込み・二重のNOTの除去・バッファの短絡・冪等則と吸収則による簡単化
+/// 取り除いたcellの出力を読んでいたところは置き換え先のbitを直接読む
+/// 使われなくなったcellはそのまま残す
+pub fn simplify(netlist: &Netlist) -> Result<(Netlist, OptReport), &'static str> {
+    let order = netlist.topo_order()?;
+    let mut report = OptReport {
+        before: netlist.cells.len(),
+        ..Default::default()
+    };
+    // 取り除いたcellの出力 -> 置き換え先
+    let mut subst: HashMap<WireId, Bit> = HashMap::new();
+    let mut defs: Defs = HashMap::new();
+    // NOTの入力 -> 出力。同じ入力のNOTを使い回す
+    let mut nots: HashMap<Bit, WireId> = HashMap::new();
+    let mut kept: Vec<Option<Cell>> = vec![None; netlist.cells.len()];
+    for &i in order.iter() {
+        let cell = &netlist.cells[i];
+        let ins: Vec<Bit> = cell.inputs.iter().map(|b| resolve(&subst, b)).collect();
+        let to = match reduce(cell.type_name, &ins, &defs) {
+            Reduced::Const(c) => {
+                report.constants += 1;
+                Bit::Const(c)
+            }
+            Reduced::Bit(x) => {
+                report.buffers += 1;
+                x
+            }
+            Reduced::Not(x) => {
+                if let Some(y) = inverse(&defs, x) {
+                    report.inverters += 1;
+                    y
+                } else if let Some(&out) = nots.get(&x) {
+                    report.buffers += 1;
+                    Bit::Wire(out)
+                } else {
+                    if cell.type_name != CellType::Not {
+                        report.rewritten += 1;
+                    }
+                    nots.insert(x, cell.output);
+                    defs.insert(cell.output, (CellType::Not, vec![x]));
+                    kept[i] = Some(Cell {
+                        type_name: CellType::Not,
+                        inputs: vec![x],
+                        ..cell.clone()
+                    });
+                    continue;
+                }
+            }
+            Reduced::Keep => {
+                defs.insert(cell.output, (cell.type_name, ins.clone()));
+                kept[i] = Some(Cell {
+                    inputs: ins,
+                    ..cell.clone()
+                });
+                continue;
+            }
+        };
+        subst.insert(cell.output, to);
+    }
+
+    let cells: Vec<Cell> = kept.into_iter().flatten().collect();
+    report.after = cells.len();
+    Ok((rewire(netlist, cells, &subst), report))
+}
+
+/// # Sammary
+/// 構造ハッシュ
+/// 可換なゲートの入力を並べ替えたうえで(種類, 入力)が同じcellを一つにまとめ、
+/// 取り除いたcellの出力を読んでいたところは残したcellの出力を読む
+pub fn strash(netlist: &Netlist) -> Result<(Netlist, OptReport), &'static str> {
+    let order = netlist.topo_order()?;
+    let mut report = OptReport {
+        before: netlist.cells.len(),
+        ..Default::default()
+    };
+    let mut subst: HashMap<WireId, Bit> = HashMap::new();
+    let mut table: HashMap<(CellType, Vec<Bit>), WireId> = HashMap::new();
+    let mut kept: Vec<Option<Cell>> = vec![None; netlist.cells.len()];
+    for &i in order.iter() {
+        let cell = &netlist.cells[i];
+        let mut ins: Vec<Bit> = cell.inputs.iter().map(|b| resolve(&subst, b)).collect();
+        // NOT以外のゲートはすべて可換
+        ins.sort_unstable();
+        match table.get(&(cell.type_name, ins.clone())) {
+            Some(&out) => {
+                report.merged += 1;
+                subst.insert(cell.output, Bit::Wire(out));
+            }
+            None => {
+                table.insert((cell.type_name, ins.clone()), cell.output);
+                kept[i] = Some(Cell {
+                    inputs: ins,
+                    ..cell.clone()
+                });
+            }
+        }
+    }
+
+    let cells: Vec<Cell> = kept.into_iter().flatten().collect();
+    report.after = cells.len();
+    Ok((rewire(netlist, cells, &subst), report))
+}
+
+/// # Sammary
+/// 出力ポートとメモリの入力のファンインにないcellを取り除く
+/// ネット名に残った取り除いたcellの出力は、どこにも駆動されないwireになる
+pub fn sweep(netlist: &Netlist) -> (Netlist, OptReport) {
+    let drivers = netlist.drivers();
+    let mut stack: Vec<WireId> = netlist.output_ids();
+    for mem in netlist.memories.iter() {
+        for port in mem.read_ports.iter() {
+            stack.extend(port.addr.iter().chain([&port.en]).filter_map(Bit::wireid));
+        }
+        for port in mem.write_ports.iter() {
+            let bits = port
+                .en
+                .iter()
+                .chain(port.addr.iter())
+                .chain(port.data.iter());
+            stack.extend(bits.filter_map(Bit::wireid));
+        }
+    }
+    let mut live = vec![false; netlist.cells.len()];
+    while let Some(id) = stack.pop() {
+        if let Some(&c) = drivers.get(&id) {
+            if !live[c] {
+                live[c] = true;
+                stack.extend(netlist.cells[c].inputs.iter().filter_map(Bit::wireid));
+            }
+        }
+    }
+
+    let mut swept = netlist.clone();
+    let mut report = OptReport {
+        before: netlist.cells.len(),
+        ..Default::default()
+    };
+    swept.cells.clear();
+    for (cell, live) in netlist.cells.iter().zip(live) {
+        if live {
+            swept.cells.push(cell.clone());
+        } else {
+            report.swept.push(cell.name.clone());
+        }
+    }
+    report.after = swept.cells.len();
+    (swept, report)
+}
+
+/// simplify, strash, sweepを順にかける
+pub fn optimize(netlist: &Netlist) -> Result<(Netlist, OptReport), &'static str> {
+    let (simplified, report) = simplify(netlist)?;
+    let (hashed, next) = strash(&simplified)?;
+    let report = report.then(next);
+    let (swept, next) = sweep(&hashed);
+    Ok((swept, report.then(next)))
+}
+
+/// cellsを入れ替え、cellの入力・ポート・メモリ・ネット名のbitを置き換え先につなぎ直す
+pub(crate) fn rewire(netlist: &Netlist, cells: Vec<Cell>, subst: &HashMap<WireId, Bit>) -> Netlist {
+    let map = |bits: &[Bit]| -> Vec<Bit> { bits.iter().map(|b| resolve(subst, b)).collect() };
+    let mut simplified = netlist.clone();
+    simplified.cells = cells;
+    for cell in simplified.cells.iter_mut() {
+        cell.inputs = map(&cell.inputs);
+    }
+    for port in simplified.ports.iter_mut() {
+        port.bits = map(&port.bits);
+    }
+    for mem in simplified.memories.iter_mut() {
+        for port in mem.read_ports.iter_mut() {
+            port.addr = map(&port.addr);
+            port.en = resolve(subst, &port.en);
+        }
+        for port in mem.write_ports.iter_mut() {
+            port.en = map(&port.en);
+            port.addr = map(&port.addr);
+            port.data = map(&port.data);
+        }
+    }
+    for (_, bits) in simplified.netnames.iter_mut() {
+        *bits = map(bits);
+    }
+    simplified
+}
+
+/// 置き換え先は解決済みなので一度引けばよい
+fn resolve(subst: &HashMap<WireId, Bit>, bit: &Bit) -> Bit {
+    bit.wireid()
+        .and_then(|id| subst.get(&id))
+        .copied()
+        .unwrap_or(*bit)
+}
+
+impl<T> Circuit<T> {
+    /// 簡単化したnetlistから作り直した回路
+    pub fn simplified(&self) -> Result<(Circuit<T>, OptReport), &'static str>
+    where
+        T: LogicOps + LogicConst,
+    {
+        let (netlist, report) = simplify(self.netlist())?;
+        let circuit = Circuit::from_netlist(netlist).ok_or("cannot build circuit")?;
+        Ok((circuit, report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        equiv::{equiv, EquivOptions, Equivalence},
+        yosys_parse::Direction,
+    };
+
+    #[test]
+    fn simplify_ripple() {
+        let mut netlist =
+            Netlist::from_yosys(include_str!("../yosys/sample/ripple_res.json")).unwrap();
+        // Bを0に固定すると X = A, carry = 0 になる
+        let b = netlist.port("B").unwrap().bits.clone();
+        for cell in netlist.cells.iter_mut() {
+            for bit in cell.inputs.iter_mut() {
+                if b.contains(bit) {
+                    *bit = Bit::Const(false);
+                }
+            }
+        }
+        let (simplified, report) = simplify(&netlist).unwrap();
+        assert_eq!(report.before, netlist.cells.len());
+        assert!(report.constants > 0);
+        assert!(report.removed() > 0);
+        assert_eq!(
+            simplified.port("carry").unwrap().bits,
+            vec![Bit::Const(false)]
+        );
+        assert_eq!(
+            equiv(&netlist, &simplified, &EquivOptions::default()),
+            Ok(Equivalence::Equivalent)
+        );
+        // XはAをそのまま出すが、読み出してもAは消えない
+        let mut circuit = Circuit::<bool>::from_netlist(simplified).unwrap();
+        let width = b.len();
+        circuit.set_port("B", vec![false; width]).unwrap();
+        for a in [0b1010_u64, 0b0111] {
+            let bits: Vec<bool> = (0..width).map(|i| a >> i & 1 == 1).collect();
+            circuit.set_port("A", bits.clone()).unwrap();
+            for _ in 0..2 {
+                circuit.evaluate().unwrap();
+                assert_eq!(circuit.get_port("X").unwrap(), bits);
+            }
+        }
+
+        // y = !!a0 & a0 は a0 になる
+        let a0 = netlist.port("A").unwrap().bits[0];
+        let next = netlist.max_wireid();
+        for (i, (type_name, inputs)) in [
+            (CellType::Not, vec![a0]),
+            (CellType::Not, vec![Bit::Wire(next + 1)]),
+            (CellType::And, vec![Bit::Wire(next + 2), a0]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            netlist.cells.push(Cell {
+                name: format!("extra{}", i),
+                type_name,
+                inputs,
+                output: next + 1 + i as WireId,
+            });
+        }
+        netlist
+            .netnames
+            .push(("y".to_string(), vec![Bit::Wire(next + 3)]));
+        let (simplified, report) = simplify(&netlist).unwrap();
+        assert_eq!(report.inverters, 1);
+        let y = &simplified
+            .netnames
+            .iter()
+            .find(|(n, _)| n == "y")
+            .unwrap()
+            .1;
+        assert_eq!(y, &vec![a0]);
+    }
+
+    #[test]
+    fn strash_duplicates() {
+        let netlist = Netlist::from_yosys(include_str!("../yosys/sample/ripple_res.json")).unwrap();
+        // 全cellの複製を入力を入れ替えてつなぎ、出力を複製側に付け替える
+        let shift = netlist.max_wireid();
+        let drivers = netlist.drivers();
+        let copy = |b: &Bit| match b {
+            Bit::Wire(id) if drivers.contains_key(id) => Bit::Wire(id + shift),
+            b => *b,
+        };
+        let mut doubled = netlist.clone();
+        for cell in netlist.cells.iter() {
+            doubled.cells.push(Cell {
+                name: format!("{}_copy", cell.name),
+                inputs: cell.inputs.iter().rev().map(copy).collect(),
+                output: cell.output + shift,
+                ..cell.clone()
+            });
+        }
+        for port in doubled
+            .ports
+            .iter_mut()
+            .filter(|p| p.direction == Direction::Out)
+        {
+            port.bits = port.bits.iter().map(copy).collect();
+        }
+
+        let (hashed, report) = strash(&doubled).unwrap();
+        assert_eq!(report.merged, netlist.cells.len());
+        assert_eq!(hashed.cells.len(), netlist.cells.len());
+        assert_eq!(
+            equiv(&doubled, &hashed, &EquivOptions::default()),
+            Ok(Equivalence::Equivalent)
+        );
+        assert_eq!(optimize(&doubled).unwrap().1.after, netlist.cells.len());
+    }
+
+    #[test]
+    fn sweep_dead() {
+        let mut netlist =
+            Netlist::from_yosys(include_str!("../yosys/sample/ripple_res.json")).unwrap();
+        let before = netlist.cells.len();
+        let a0 = netlist.port("A").unwrap().bits[0];
+        let next = netlist.max_wireid();
+        netlist.cells.push(Cell {
+            name: "dead0".to_string(),
+            type_name: CellType::Not,
+            inputs: vec![a0],
+            output: next + 1,
+        });
+        netlist.cells.push(Cell {
+            name: "dead1".to_string(),
+            type_name: CellType::And,
+            inputs: vec![a0, Bit::Wire(next + 1)],
+            output: next + 2,
+        });
+        let (swept, report) = sweep(&netlist);
+        assert_eq!(report.swept, vec!["dead0", "dead1"]);
+        assert_eq!(swept.cells.len(), before);
+        assert!(report.to_string().contains("    dead1\n"));
+        // simplifyで使われなくなったNOTもまとめて取り除く
+        let (_, report) = optimize(&netlist).unwrap();
+        assert_eq!(report.after, before);
+    }
+}
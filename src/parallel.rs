@@ -0,0 +1,134 @@
+use crate::{
+    culc_node,
+    traits::{LogicConst, LogicOps},
+    Circuit,
+};
+use std::{
+    any::Any,
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Barrier, Mutex,
+    },
+    thread,
+};
+
+/// evaluate_parallelの設定
+#[derive(Debug, Clone, Copy)]
+pub struct ParallelOptions {
+    /// 計算に使うスレッドの数(呼び出したスレッドを含む)
+    pub threads: usize,
+    /// スレッドが一度に取るnodeの数
+    /// 小さいほど負荷が均等になり、大きいほど取り合いの手間が減る
+    pub chunk: usize,
+}
+impl Default for ParallelOptions {
+    fn default() -> Self {
+        ParallelOptions {
+            threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            chunk: 256,
+        }
+    }
+}
+
+impl<T> Circuit<T> {
+    /// # Sammary
+    /// 依存の深さの浅い段から順に、段の中のnodeをchunk個ずつスレッドで取り合って計算する
+    /// 段の間はBarrierで待ち合わせる。結果はevaluate()と同じになる
+    /// 一番広い段でもchunk個に満たなければ現在のスレッドだけで計算する
+    pub fn evaluate_parallel(&mut self, options: &ParallelOptions) -> Result<(), &'static str>
+    where
+        T: LogicOps + LogicConst + Send + Sync,
+    {
+        let chunk = options.chunk.max(1);
+        let widest = self.levels.iter().map(Vec::len).max().unwrap_or(0);
+        let threads = options.threads.min(widest.div_ceil(chunk)).max(1);
+        if threads == 1 {
+            return self.evaluate();
+        }
+        let actions = self.prepare()?;
+        self.stale.iter_mut().for_each(|s| *s = false);
+
+        let (nodes, levels, evaluated) = (&self.nodes, &self.levels, &*self.evaluated);
+        let next: Vec<AtomicUsize> = levels.iter().map(|_| AtomicUsize::new(0)).collect();
+        let barrier = Barrier::new(threads);
+        let failed: Mutex<Option<&'static str>> = Mutex::new(None);
+        let panicked: Mutex<Option<Box<dyn Any + Send>>> = Mutex::new(None);
+        let work = || {
+            for (level, next) in levels.iter().zip(next.iter()) {
+                loop {
+                    let start = next.fetch_add(chunk, Ordering::Relaxed);
+                    if start >= level.len() {
+                        break;
+                    }
+                    let end = (start + chunk).min(level.len());
+                    // 落ちたスレッドも待ち合わせに来ないと他のスレッドが止まってしまう
+                    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                        level[start..end]
+                            .iter()
+                            .try_for_each(|&i| culc_node(&nodes[i], actions[i], evaluated))
+                    }));
+                    match res {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => {
+                            failed.lock().unwrap().get_or_insert(e);
+                        }
+                        Err(e) => {
+                            panicked.lock().unwrap().get_or_insert(e);
+                        }
+                    }
+                }
+                barrier.wait();
+            }
+        };
+        thread::scope(|s| {
+            for _ in 1..threads {
+                s.spawn(work);
+            }
+            work();
+        });
+        if let Some(e) = panicked.into_inner().unwrap() {
+            panic::resume_unwind(e);
+        }
+        let res = failed.into_inner().unwrap().map_or(Ok(()), Err);
+        self.settle(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::XorShift;
+
+    #[test]
+    fn same_as_evaluate() {
+        let json = include_str!("../yosys/sample/ripple_res.json");
+        let mut single = Circuit::<u64>::from_yosys(json).unwrap();
+        let mut circuit = Circuit::<u64>::from_yosys(json).unwrap();
+        let mut rng = XorShift(7);
+        for options in [
+            ParallelOptions {
+                threads: 4,
+                chunk: 1,
+            },
+            ParallelOptions {
+                threads: 3,
+                chunk: 2,
+            },
+            ParallelOptions::default(),
+        ] {
+            for id in single.input_ids() {
+                let val = rng.next();
+                single.set_input(id, Box::new(val)).unwrap();
+                circuit.set_input(id, Box::new(val)).unwrap();
+            }
+            single.evaluate().unwrap();
+            circuit.evaluate_parallel(&options).unwrap();
+            assert_eq!(circuit.evaluated(), circuit.node_count());
+            for name in ["X", "carry"] {
+                assert_eq!(circuit.get_port(name), single.get_port(name));
+            }
+        }
+    }
+}
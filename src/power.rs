@@ -0,0 +1,197 @@
+use crate::{
+    netlist::{Bit, Netlist},
+    vectors::VectorSet,
+    yosys_parse::{CellType, WireId},
+    Circuit,
+};
+use std::{collections::HashMap, fmt::Write};
+
+/// # Sammary
+/// ベクタ列を流したときの各wireの値の変化の回数
+/// メモリを含む回路ではベクタ毎にtick()して1サイクルとみなす
+#[derive(Debug, Clone, Default)]
+pub struct Activity {
+    /// 流したベクタ(サイクル)の数
+    pub cycles: usize,
+    pub toggles: HashMap<WireId, usize>,
+}
+impl Activity {
+    pub fn measure(circuit: &mut Circuit<bool>, vectors: &VectorSet) -> Result<Self, &'static str> {
+        let ids = circuit.wire_ids();
+        let sequential = !circuit.netlist().memories.is_empty();
+        let mut activity = Activity {
+            cycles: 0,
+            toggles: ids.iter().map(|&id| (id, 0)).collect(),
+        };
+        let mut last: Option<Vec<Option<bool>>> = None;
+        for pattern in vectors.patterns.iter() {
+            for (name, values) in vectors.ports.iter().zip(pattern.iter()) {
+                circuit.set_port(name, values.clone())?;
+            }
+            circuit.evaluate()?;
+            let now: Vec<Option<bool>> = ids
+                .iter()
+                .map(|&id| circuit.peek(id))
+                .collect::<Result<_, _>>()?;
+            if let Some(last) = last {
+                for ((id, a), b) in ids.iter().zip(last.iter()).zip(now.iter()) {
+                    if a != b {
+                        *activity.toggles.get_mut(id).unwrap() += 1;
+                    }
+                }
+            }
+            last = Some(now);
+            activity.cycles += 1;
+            if sequential {
+                circuit.tick()?;
+            }
+        }
+        Ok(activity)
+    }
+    /// 1サイクルあたりの変化の回数
+    pub fn toggle_rate(&self, id: WireId) -> f64 {
+        if self.cycles < 2 {
+            return 0.0;
+        }
+        *self.toggles.get(&id).unwrap_or(&0) as f64 / (self.cycles - 1) as f64
+    }
+    /// 変化の多い順の(ネット名, 変化率)
+    pub fn ranked(&self, netlist: &Netlist) -> Vec<(String, f64)> {
+        let mut ids: Vec<WireId> = self.toggles.keys().copied().collect();
+        ids.sort_by(|a, b| self.toggles[b].cmp(&self.toggles[a]).then_with(|| a.cmp(b)));
+        ids.into_iter()
+            .map(|id| (netlist.wire_label(id), self.toggle_rate(id)))
+            .collect()
+    }
+}
+
+/// # Sammary
+/// ネットの容量の重み
+/// ネットの容量 = 駆動するセルの種類の出力容量 + pin × ファンアウトのピン数
+#[derive(Debug, Clone)]
+pub struct CapTable {
+    caps: HashMap<CellType, f64>,
+    /// 表にない種類のセルの出力容量
+    pub default: f64,
+    /// セルの入力ピン1つあたりの容量
+    pub pin: f64,
+    /// 外部入力の容量
+    pub input: f64,
+}
+impl Default for CapTable {
+    fn default() -> Self {
+        CapTable {
+            caps: HashMap::new(),
+            default: 1.0,
+            pin: 1.0,
+            input: 0.0,
+        }
+    }
+}
+impl CapTable {
+    pub fn set(&mut self, type_name: CellType, cap: f64) {
+        self.caps.insert(type_name, cap);
+    }
+    pub fn cap(&self, type_name: CellType) -> f64 {
+        *self.caps.get(&type_name).unwrap_or(&self.default)
+    }
+}
+
+/// # contents
+/// - nets: (wireid, 電力)。大きい順
+/// - by_type: 駆動セルの種類毎の(変化の回数, 電力)。外部入力やメモリはNone
+pub struct PowerReport {
+    pub total: f64,
+    pub nets: Vec<(WireId, f64)>,
+    pub by_type: Vec<(Option<CellType>, usize, f64)>,
+}
+impl PowerReport {
+    pub fn summary(&self, netlist: &Netlist, top: usize) -> String {
+        let mut out = String::new();
+        writeln!(out, "total: {:.6}", self.total).unwrap();
+        for (type_name, toggles, power) in self.by_type.iter() {
+            let name = type_name.map_or("(input)", |t| t.name());
+            writeln!(
+                out,
+                "  {:<10} toggles {:>8}  power {:.6}",
+                name, toggles, power
+            )
+            .unwrap();
+        }
+        for (id, power) in self.nets.iter().take(top) {
+            writeln!(out, "  {:.6}  {}", power, netlist.wire_label(*id)).unwrap();
+        }
+        out
+    }
+}
+
+/// # Sammary
+/// 動的電力 P = 1/2 · C · Vdd² · f · (変化率) をネット毎に足し合わせる
+pub fn estimate(
+    netlist: &Netlist,
+    activity: &Activity,
+    caps: &CapTable,
+    vdd: f64,
+    freq: f64,
+) -> PowerReport {
+    let drivers = netlist.drivers();
+    let mut fanout: HashMap<WireId, usize> = HashMap::new();
+    for id in netlist
+        .cells
+        .iter()
+        .flat_map(|c| c.inputs.iter().filter_map(Bit::wireid))
+    {
+        *fanout.entry(id).or_default() += 1;
+    }
+
+    let mut nets = Vec::new();
+    let mut by_type: HashMap<Option<CellType>, (usize, f64)> = HashMap::new();
+    for (&id, &toggles) in activity.toggles.iter() {
+        let type_name = drivers.get(&id).map(|&i| netlist.cells[i].type_name);
+        let cap = type_name.map_or(caps.input, |t| caps.cap(t))
+            + caps.pin * *fanout.get(&id).unwrap_or(&0) as f64;
+        let power = 0.5 * cap * vdd * vdd * freq * activity.toggle_rate(id);
+        nets.push((id, power));
+        let entry = by_type.entry(type_name).or_default();
+        entry.0 += toggles;
+        entry.1 += power;
+    }
+    nets.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let mut by_type: Vec<(Option<CellType>, usize, f64)> =
+        by_type.into_iter().map(|(t, (n, p))| (t, n, p)).collect();
+    by_type.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+    PowerReport {
+        total: nets.iter().map(|(_, p)| p).sum(),
+        nets,
+        by_type,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggles() {
+        let mut circuit = Circuit::<bool>::from_yosys(include_str!("yosys_sample.v")).unwrap();
+        // A[0]だけが毎回変わる
+        let vectors = VectorSet::parse("A B\n0000 0000\n0001 0000\n0000 0000\n").unwrap();
+        let activity = Activity::measure(&mut circuit, &vectors).unwrap();
+        assert_eq!(activity.cycles, 3);
+        let netlist = circuit.netlist();
+        let a0 = netlist.port("A").unwrap().bits[0].wireid().unwrap();
+        let b0 = netlist.port("B").unwrap().bits[0].wireid().unwrap();
+        assert_eq!(activity.toggle_rate(a0), 1.0);
+        assert_eq!(activity.toggle_rate(b0), 0.0);
+        assert_eq!(activity.ranked(netlist)[0].1, 1.0);
+
+        let report = estimate(netlist, &activity, &CapTable::default(), 1.0, 1.0);
+        let (top, power) = report.nets[0];
+        assert!(power > 0.0);
+        assert!(activity.toggles[&top] > 0);
+        let sum: f64 = report.by_type.iter().map(|(_, _, p)| p).sum();
+        assert!((sum - report.total).abs() < 1e-9);
+        assert!(report.summary(netlist, 3).starts_with("total: "));
+    }
+}
@@ -0,0 +1,163 @@
+use crate::{
+    netlist::{Bit, Netlist},
+    traits::{LogicConst, LogicOps},
+    util::XorShift,
+    yosys_parse::WireId,
+    Circuit,
+};
+use std::collections::HashMap;
+
+/// # Sammary
+/// 値が1である確率
+/// ゲートの入力同士は独立とみなして計算するので、再収斂のある回路では近似になる
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Prob(pub f64);
+impl LogicOps for Prob {
+    fn lgc_nand(&self, rhs: &Self) -> Self {
+        self.lgc_and(rhs).lgc_not()
+    }
+    fn lgc_and(&self, rhs: &Self) -> Self {
+        Prob(self.0 * rhs.0)
+    }
+    fn lgc_nor(&self, rhs: &Self) -> Self {
+        self.lgc_or(rhs).lgc_not()
+    }
+    fn lgc_or(&self, rhs: &Self) -> Self {
+        Prob(self.0 + rhs.0 - self.0 * rhs.0)
+    }
+    fn lgc_nxor(&self, rhs: &Self) -> Self {
+        self.lgc_xor(rhs).lgc_not()
+    }
+    fn lgc_xor(&self, rhs: &Self) -> Self {
+        Prob(self.0 + rhs.0 - 2.0 * self.0 * rhs.0)
+    }
+    fn lgc_not(&self) -> Self {
+        Prob(1.0 - self.0)
+    }
+    fn lgc_buf(&self) -> Self {
+        *self
+    }
+}
+impl LogicConst for Prob {
+    fn lgc_const(val: bool) -> Self {
+        Prob(if val { 1.0 } else { 0.0 })
+    }
+    fn lgc_level(&self) -> Option<bool> {
+        if self.0 == 0.0 {
+            Some(false)
+        } else if self.0 == 1.0 {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+/// 入力ポートのbit毎の確率。指定のないbitは0.5
+fn input_probs(
+    netlist: &Netlist,
+    inputs: &[(String, Vec<f64>)],
+) -> Result<HashMap<WireId, f64>, &'static str> {
+    let mut probs: HashMap<WireId, f64> = netlist
+        .input_ids()
+        .into_iter()
+        .map(|id| (id, 0.5))
+        .collect();
+    for (name, values) in inputs.iter() {
+        let port = netlist.port(name).ok_or("selected port is not found")?;
+        if port.bits.len() != values.len() {
+            return Err("width mismatch");
+        }
+        for (bit, &p) in port.bits.iter().zip(values.iter()) {
+            if let Bit::Wire(id) = bit {
+                probs.insert(*id, p);
+            }
+        }
+    }
+    Ok(probs)
+}
+
+/// # Sammary
+/// 入力の確率を独立とみなして回路に流し、全wireの1になる確率を求める
+pub fn propagate(
+    netlist: &Netlist,
+    inputs: &[(String, Vec<f64>)],
+) -> Result<HashMap<WireId, f64>, &'static str> {
+    let mut circuit =
+        Circuit::<Prob>::from_netlist(netlist.clone()).ok_or("cannot build circuit")?;
+    for (id, p) in input_probs(netlist, inputs)? {
+        circuit.set_input(id, Box::new(Prob(p)))?;
+    }
+    circuit.evaluate()?;
+    let mut probs = HashMap::new();
+    for id in circuit.wire_ids() {
+        let p = circuit.peek(id)?.ok_or("wire is empty")?;
+        probs.insert(id, p.0);
+    }
+    Ok(probs)
+}
+
+/// # Sammary
+/// 入力をその確率で1にした乱数パターンを64本ずつu64で流し、
+/// 全wireで1になった割合を数える。再収斂による相関も反映される
+pub fn monte_carlo(
+    netlist: &Netlist,
+    inputs: &[(String, Vec<f64>)],
+    rounds: usize,
+    seed: u64,
+) -> Result<HashMap<WireId, f64>, &'static str> {
+    // HashMapの順で乱数を引くとseedが同じでも結果が変わるので、id順に並べておく
+    let mut probs: Vec<(WireId, f64)> = input_probs(netlist, inputs)?.into_iter().collect();
+    probs.sort_unstable_by_key(|&(id, _)| id);
+    let mut circuit =
+        Circuit::<u64>::from_netlist(netlist.clone()).ok_or("cannot build circuit")?;
+    let ids = circuit.wire_ids();
+    let mut ones: HashMap<WireId, u64> = ids.iter().map(|&id| (id, 0)).collect();
+    let mut rng = XorShift(seed | 1);
+    for _ in 0..rounds {
+        for &(id, p) in probs.iter() {
+            let mut lanes = 0u64;
+            for lane in 0..64 {
+                let r = (rng.next() >> 11) as f64 / (1u64 << 53) as f64;
+                if r < p {
+                    lanes |= 1 << lane;
+                }
+            }
+            circuit.set_input(id, Box::new(lanes))?;
+        }
+        circuit.evaluate()?;
+        for &id in ids.iter() {
+            let lanes = circuit.peek(id)?.ok_or("wire is empty")?;
+            *ones.get_mut(&id).unwrap() += lanes.count_ones() as u64;
+        }
+    }
+    let total = (rounds * 64).max(1) as f64;
+    Ok(ones
+        .into_iter()
+        .map(|(id, n)| (id, n as f64 / total))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::hazard_sample;
+
+    #[test]
+    fn probabilities() {
+        // y = A[0] & !A[0] は常に0だが、独立を仮定すると1/4になる
+        let (netlist, y) = hazard_sample();
+        let x0 = netlist.port("X").unwrap().bits[0].wireid().unwrap();
+
+        let inputs = [("B".to_string(), vec![0.0; 4])];
+        let exact = propagate(&netlist, &inputs).unwrap();
+        assert_eq!(exact[&x0], 0.5);
+        assert_eq!(exact[&y], 0.25);
+
+        let sampled = monte_carlo(&netlist, &inputs, 64, 1).unwrap();
+        assert!((sampled[&x0] - 0.5).abs() < 0.05);
+        assert_eq!(sampled[&y], 0.0);
+        // 同じseedなら同じ結果になる
+        assert_eq!(monte_carlo(&netlist, &inputs, 64, 1).unwrap(), sampled);
+    }
+}
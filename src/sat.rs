@@ -0,0 +1,634 @@
+use crate::{
+    cnf::{Cnf, Lit},
+    netlist::{Bit, Netlist, PortValues},
+    yosys_parse::Direction,
+    Circuit,
+};
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SatResult {
+    /// model[v]が変数v(1始まり)の値。model[0]は使わない
+    Sat(Vec<bool>),
+    Unsat,
+    /// 衝突回数の上限に達した
+    Unknown,
+}
+
+/// 内部のリテラル表現 2 * var + 否定
+type Code = u32;
+fn code(l: Lit) -> Code {
+    ((l.unsigned_abs() - 1) << 1) | (l < 0) as u32
+}
+fn var(c: Code) -> usize {
+    (c >> 1) as usize
+}
+
+#[derive(PartialEq)]
+struct Activity(f64, usize);
+impl Eq for Activity {}
+impl PartialOrd for Activity {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Activity {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .partial_cmp(&other.0)
+            .unwrap_or(Ordering::Equal)
+            .then(other.1.cmp(&self.1))
+    }
+}
+
+/// # Sammary
+/// 小さなCDCLソルバ
+/// # contents
+/// - watches: リテラルが偽になったときに見る節(2-watched literal)
+/// - 衝突時は1UIPで節を学習し、VSIDS風の活性度で変数を選ぶ
+/// - 再始動はLuby列
+pub struct Solver {
+    num_vars: usize,
+    clauses: Vec<Vec<Code>>,
+    watches: Vec<Vec<usize>>,
+    /// 変数毎 -1:未割当 0:偽 1:真
+    assign: Vec<i8>,
+    level: Vec<usize>,
+    reason: Vec<Option<usize>>,
+    trail: Vec<Code>,
+    trail_lim: Vec<usize>,
+    qhead: usize,
+    activity: Vec<f64>,
+    var_inc: f64,
+    order: BinaryHeap<Activity>,
+    phase: Vec<bool>,
+    unsat: bool,
+    pub conflict_limit: Option<u64>,
+    pub conflicts: u64,
+}
+impl Solver {
+    pub fn new(num_vars: usize) -> Self {
+        Solver {
+            num_vars,
+            clauses: Vec::new(),
+            watches: vec![Vec::new(); num_vars * 2],
+            assign: vec![-1; num_vars],
+            level: vec![0; num_vars],
+            reason: vec![None; num_vars],
+            trail: Vec::new(),
+            trail_lim: Vec::new(),
+            qhead: 0,
+            activity: vec![0.0; num_vars],
+            var_inc: 1.0,
+            order: (0..num_vars).map(|v| Activity(0.0, v)).collect(),
+            phase: vec![false; num_vars],
+            unsat: false,
+            conflict_limit: None,
+            conflicts: 0,
+        }
+    }
+    pub fn from_cnf(cnf: &Cnf) -> Self {
+        let mut solver = Self::new(cnf.num_vars);
+        for clause in cnf.clauses.iter() {
+            solver.add_clause(clause);
+        }
+        solver
+    }
+    pub fn num_vars(&self) -> usize {
+        self.num_vars
+    }
+    /// 変数を追加して番号(1始まり)を返す
+    pub fn new_var(&mut self) -> Lit {
+        self.num_vars += 1;
+        self.watches.push(Vec::new());
+        self.watches.push(Vec::new());
+        self.assign.push(-1);
+        self.level.push(0);
+        self.reason.push(None);
+        self.activity.push(0.0);
+        self.phase.push(false);
+        self.order.push(Activity(0.0, self.num_vars - 1));
+        self.num_vars as Lit
+    }
+    fn value(&self, c: Code) -> i8 {
+        match self.assign[var(c)] {
+            -1 => -1,
+            v => v ^ (c & 1) as i8,
+        }
+    }
+    fn decision_level(&self) -> usize {
+        self.trail_lim.len()
+    }
+    fn enqueue(&mut self, c: Code, reason: Option<usize>) {
+        let v = var(c);
+        self.assign[v] = 1 ^ (c & 1) as i8;
+        self.level[v] = self.decision_level();
+        self.reason[v] = reason;
+        self.trail.push(c);
+    }
+    /// 節を追加する。矛盾が確定したらfalse
+    pub fn add_clause(&mut self, lits: &[Lit]) -> bool {
+        if self.unsat {
+            return false;
+        }
+        self.backtrack(0);
+        let mut clause: Vec<Code> = lits.iter().map(|&l| code(l)).collect();
+        clause.sort_unstable();
+        clause.dedup();
+        // 恒真な節と、すでに真のリテラルを含む節は捨てる
+        if clause.windows(2).any(|w| w[0] ^ 1 == w[1]) || clause.iter().any(|&c| self.value(c) == 1)
+        {
+            return true;
+        }
+        clause.retain(|&c| self.value(c) != 0);
+        match clause.len() {
+            0 => {
+                self.unsat = true;
+                false
+            }
+            1 => {
+                self.enqueue(clause[0], None);
+                if self.propagate().is_some() {
+                    self.unsat = true;
+                }
+                !self.unsat
+            }
+            _ => {
+                self.attach(clause);
+                true
+            }
+        }
+    }
+    fn attach(&mut self, clause: Vec<Code>) -> usize {
+        let i = self.clauses.len();
+        self.watches[clause[0] as usize].push(i);
+        self.watches[clause[1] as usize].push(i);
+        self.clauses.push(clause);
+        i
+    }
+    /// 衝突した節を返す
+    fn propagate(&mut self) -> Option<usize> {
+        while self.qhead < self.trail.len() {
+            let p = self.trail[self.qhead];
+            self.qhead += 1;
+            let false_lit = p ^ 1;
+            let mut ws = std::mem::take(&mut self.watches[false_lit as usize]);
+            let mut i = 0;
+            while i < ws.len() {
+                let ci = ws[i];
+                if self.clauses[ci][0] == false_lit {
+                    self.clauses[ci].swap(0, 1);
+                }
+                let first = self.clauses[ci][0];
+                if self.value(first) == 1 {
+                    i += 1;
+                    continue;
+                }
+                let len = self.clauses[ci].len();
+                if let Some(k) = (2..len).find(|&k| self.value(self.clauses[ci][k]) != 0) {
+                    self.clauses[ci].swap(1, k);
+                    let w = self.clauses[ci][1];
+                    self.watches[w as usize].push(ci);
+                    ws.swap_remove(i);
+                    continue;
+                }
+                if self.value(first) == 0 {
+                    self.watches[false_lit as usize] = ws;
+                    self.qhead = self.trail.len();
+                    return Some(ci);
+                }
+                self.enqueue(first, Some(ci));
+                i += 1;
+            }
+            self.watches[false_lit as usize] = ws;
+        }
+        None
+    }
+    fn bump(&mut self, v: usize) {
+        self.activity[v] += self.var_inc;
+        if self.activity[v] > 1e100 {
+            for a in self.activity.iter_mut() {
+                *a *= 1e-100;
+            }
+            self.var_inc *= 1e-100;
+            self.order = (0..self.num_vars)
+                .filter(|&v| self.assign[v] == -1)
+                .map(|v| Activity(self.activity[v], v))
+                .collect();
+        } else if self.assign[v] == -1 {
+            self.order.push(Activity(self.activity[v], v));
+        }
+    }
+    /// 1UIPで学習節と戻り先のlevelを求める
+    fn analyze(&mut self, confl: usize) -> (Vec<Code>, usize) {
+        let mut seen = vec![false; self.num_vars];
+        let mut learnt: Vec<Code> = vec![0];
+        let mut counter = 0;
+        let mut p: Option<Code> = None;
+        let mut idx = self.trail.len();
+        let mut clause = confl;
+        loop {
+            for k in 0..self.clauses[clause].len() {
+                let q = self.clauses[clause][k];
+                let v = var(q);
+                if Some(v) == p.map(var) || seen[v] || self.level[v] == 0 {
+                    continue;
+                }
+                seen[v] = true;
+                self.bump(v);
+                if self.level[v] == self.decision_level() {
+                    counter += 1;
+                } else {
+                    learnt.push(q);
+                }
+            }
+            loop {
+                idx -= 1;
+                if seen[var(self.trail[idx])] {
+                    break;
+                }
+            }
+            let q = self.trail[idx];
+            seen[var(q)] = false;
+            counter -= 1;
+            p = Some(q);
+            if counter == 0 {
+                break;
+            }
+            clause = self.reason[var(q)].unwrap();
+        }
+        learnt[0] = p.unwrap() ^ 1;
+        let mut back = 0;
+        if learnt.len() > 1 {
+            let (k, _) = learnt
+                .iter()
+                .enumerate()
+                .skip(1)
+                .max_by_key(|(_, &c)| self.level[var(c)])
+                .unwrap();
+            learnt.swap(1, k);
+            back = self.level[var(learnt[1])];
+        }
+        (learnt, back)
+    }
+    fn backtrack(&mut self, level: usize) {
+        if self.decision_level() <= level {
+            return;
+        }
+        let lim = self.trail_lim[level];
+        for &c in self.trail[lim..].iter() {
+            let v = var(c);
+            self.phase[v] = self.assign[v] == 1;
+            self.assign[v] = -1;
+            self.reason[v] = None;
+            self.order.push(Activity(self.activity[v], v));
+        }
+        self.trail.truncate(lim);
+        self.trail_lim.truncate(level);
+        self.qhead = lim;
+    }
+    fn pick(&mut self) -> Option<usize> {
+        while let Some(Activity(a, v)) = self.order.pop() {
+            if self.assign[v] == -1 && a == self.activity[v] {
+                return Some(v);
+            }
+        }
+        (0..self.num_vars).find(|&v| self.assign[v] == -1)
+    }
+    pub fn solve(&mut self) -> SatResult {
+        self.solve_with(&[])
+    }
+    /// assumptionsを仮定して解く。節は追加されない
+    pub fn solve_with(&mut self, assumptions: &[Lit]) -> SatResult {
+        if self.unsat {
+            return SatResult::Unsat;
+        }
+        self.backtrack(0);
+        if self.propagate().is_some() {
+            self.unsat = true;
+            return SatResult::Unsat;
+        }
+        let assumptions: Vec<Code> = assumptions.iter().map(|&l| code(l)).collect();
+        let mut restart = 1;
+        let mut budget = luby(restart) * 100;
+        let start = self.conflicts;
+        loop {
+            match self.propagate() {
+                Some(confl) => {
+                    self.conflicts += 1;
+                    if self.decision_level() == 0 {
+                        self.unsat = true;
+                        return SatResult::Unsat;
+                    }
+                    let (learnt, back) = self.analyze(confl);
+                    self.backtrack(back);
+                    if learnt.len() == 1 {
+                        self.enqueue(learnt[0], None);
+                    } else {
+                        let first = learnt[0];
+                        let ci = self.attach(learnt);
+                        self.enqueue(first, Some(ci));
+                    }
+                    self.var_inc /= 0.95;
+                    if let Some(limit) = self.conflict_limit {
+                        if self.conflicts - start >= limit {
+                            self.backtrack(0);
+                            return SatResult::Unknown;
+                        }
+                    }
+                    budget -= 1;
+                    if budget == 0 {
+                        restart += 1;
+                        budget = luby(restart) * 100;
+                        self.backtrack(0);
+                    }
+                }
+                None => {
+                    // 仮定を順に決定として置く。偽になっていれば仮定の下でUnsat
+                    let level = self.decision_level();
+                    if level < assumptions.len() {
+                        let a = assumptions[level];
+                        match self.value(a) {
+                            0 => {
+                                self.backtrack(0);
+                                return SatResult::Unsat;
+                            }
+                            1 => self.trail_lim.push(self.trail.len()),
+                            _ => {
+                                self.trail_lim.push(self.trail.len());
+                                self.enqueue(a, None);
+                            }
+                        }
+                        continue;
+                    }
+                    match self.pick() {
+                        Some(v) => {
+                            self.trail_lim.push(self.trail.len());
+                            let c = ((v as Code) << 1) | (!self.phase[v]) as Code;
+                            self.enqueue(c, None);
+                        }
+                        None => {
+                            let mut model = vec![false; self.num_vars + 1];
+                            for v in 0..self.num_vars {
+                                model[v + 1] = self.assign[v] == 1;
+                            }
+                            self.backtrack(0);
+                            return SatResult::Sat(model);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Luby列の i 番目(1始まり)
+fn luby(i: u64) -> u64 {
+    let mut k = 1;
+    while (1 << k) - 1 < i {
+        k += 1;
+    }
+    if (1 << k) - 1 == i {
+        1 << (k - 1)
+    } else {
+        luby(i - (1 << (k - 1)) + 1)
+    }
+}
+
+/// # Sammary
+/// 回路に対する充足可能性の問い合わせ
+/// ポートへの制約をMSB firstのbitパターン("1x0"など、xは任意)で与える
+pub struct Query<'a> {
+    netlist: &'a Netlist,
+    cnf: Cnf,
+    extra: Vec<Vec<Lit>>,
+    error: Option<&'static str>,
+}
+impl<'a> Query<'a> {
+    pub fn new(netlist: &'a Netlist) -> Result<Self, &'static str> {
+        Ok(Query {
+            netlist,
+            cnf: Cnf::from_netlist(netlist)?,
+            extra: Vec::new(),
+            error: None,
+        })
+    }
+    fn fail(&mut self, e: &'static str) {
+        self.error.get_or_insert(e);
+    }
+    /// ポートの値をパターンに制約する
+    pub fn port(mut self, name: &str, pattern: &str) -> Self {
+        let values = match parse_pattern(pattern) {
+            Some(values) => values,
+            None => {
+                self.fail("invalid bit pattern");
+                return self;
+            }
+        };
+        if let Err(e) = self.cnf.constrain_port(name, &values) {
+            self.fail(e);
+        }
+        self
+    }
+    /// 二つのポートの値が異なる
+    pub fn differ(mut self, a: &str, b: &str) -> Self {
+        match self.pair(a, b) {
+            Ok(pairs) => {
+                let mut any = Vec::new();
+                for (x, y) in pairs {
+                    let d = self.xor(x, y);
+                    any.push(d);
+                }
+                self.extra.push(any);
+            }
+            Err(e) => self.fail(e),
+        }
+        self
+    }
+    /// 二つのポートの値が等しい
+    pub fn equal(mut self, a: &str, b: &str) -> Self {
+        match self.pair(a, b) {
+            Ok(pairs) => {
+                for (x, y) in pairs {
+                    self.extra.push(vec![-x, y]);
+                    self.extra.push(vec![x, -y]);
+                }
+            }
+            Err(e) => self.fail(e),
+        }
+        self
+    }
+    fn pair(&mut self, a: &str, b: &str) -> Result<Vec<(Lit, Lit)>, &'static str> {
+        let a = self.cnf.port_lits(a)?;
+        let b = self.cnf.port_lits(b)?;
+        if a.len() != b.len() {
+            return Err("width mismatch");
+        }
+        Ok(a.into_iter().zip(b).collect())
+    }
+    /// d <-> x ^ y となる変数dを作る
+    fn xor(&mut self, x: Lit, y: Lit) -> Lit {
+        self.cnf.num_vars += 1;
+        let d = self.cnf.num_vars as Lit;
+        self.extra.push(vec![-d, x, y]);
+        self.extra.push(vec![-d, -x, -y]);
+        self.extra.push(vec![d, -x, y]);
+        self.extra.push(vec![d, x, -y]);
+        d
+    }
+    /// 充足する割当を全ポートの値(LSB first)で返す。なければNone
+    pub fn solve(self) -> Result<Option<PortValues>, &'static str> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let mut solver = Solver::from_cnf(&self.cnf);
+        for clause in self.extra.iter() {
+            solver.add_clause(clause);
+        }
+        match solver.solve() {
+            SatResult::Sat(model) => Ok(Some(port_assignment(self.netlist, &self.cnf, &model))),
+            SatResult::Unsat => Ok(None),
+            SatResult::Unknown => Err("conflict limit exceeded"),
+        }
+    }
+}
+
+/// "10x1"(MSB first)をLSB firstのOption<bool>列に
+fn parse_pattern(pattern: &str) -> Option<Vec<Option<bool>>> {
+    pattern
+        .chars()
+        .filter(|&c| c != '_')
+        .rev()
+        .map(|c| match c {
+            '0' => Some(Some(false)),
+            '1' => Some(Some(true)),
+            'x' | 'X' | '-' | '?' => Some(None),
+            _ => None,
+        })
+        .collect()
+}
+
+/// modelを全ポートの値に直す
+pub fn port_assignment(netlist: &Netlist, cnf: &Cnf, model: &[bool]) -> PortValues {
+    let map = cnf.port_map();
+    netlist
+        .ports
+        .iter()
+        .filter(|p| p.direction != Direction::InOut)
+        .map(|p| {
+            let lits = &map.iter().find(|(n, _)| *n == p.name).unwrap().1;
+            let values = p
+                .bits
+                .iter()
+                .zip(lits.iter())
+                .map(|(b, &l)| match b {
+                    Bit::Const(c) => *c,
+                    Bit::Wire(_) => model[l.unsigned_abs() as usize] == (l > 0),
+                })
+                .collect();
+            (p.name.clone(), values)
+        })
+        .collect()
+}
+
+impl<T> Circuit<T> {
+    /// この回路に対するSATの問い合わせを作る
+    pub fn query(&self) -> Result<Query<'_>, &'static str> {
+        Query::new(self.netlist())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pigeonhole() {
+        // 3羽を2つの巣に入れることはできない
+        let p = |i: i32, j: i32| i * 2 + j + 1;
+        let mut solver = Solver::new(6);
+        for i in 0..3 {
+            solver.add_clause(&[p(i, 0), p(i, 1)]);
+        }
+        for j in 0..2 {
+            for a in 0..3 {
+                for b in a + 1..3 {
+                    solver.add_clause(&[-p(a, j), -p(b, j)]);
+                }
+            }
+        }
+        assert_eq!(solver.solve(), SatResult::Unsat);
+
+        let mut solver = Solver::new(3);
+        solver.add_clause(&[1, 2]);
+        solver.add_clause(&[-1, 3]);
+        solver.add_clause(&[-2, -3]);
+        match solver.solve() {
+            SatResult::Sat(m) => {
+                assert!((m[1] || m[2]) && (!m[1] || m[3]) && (!m[2] || !m[3]))
+            }
+            r => panic!("{:?}", r),
+        }
+        // 仮定は節として残らない
+        assert_eq!(solver.solve_with(&[1, 2]), SatResult::Unsat);
+        assert!(matches!(solver.solve_with(&[2]), SatResult::Sat(_)));
+    }
+
+    #[test]
+    fn full_adder_query() {
+        let json = include_str!("yosys_sample.v");
+        let circuit = Circuit::<bool>::from_yosys(json).unwrap();
+        let value = |ports: &Vec<(String, Vec<bool>)>, name: &str| -> u64 {
+            ports
+                .iter()
+                .find(|(n, _)| n == name)
+                .unwrap()
+                .1
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (i, &b)| acc | ((b as u64) << i))
+        };
+
+        // carryが1でXが0 -> A + B = 16
+        let res = circuit
+            .query()
+            .unwrap()
+            .port("carry", "1")
+            .port("X", "0000")
+            .solve()
+            .unwrap()
+            .unwrap();
+        assert_eq!(value(&res, "A") + value(&res, "B"), 16);
+
+        // A = 15 のときcarryを0にするBは0だけ
+        let res = circuit
+            .query()
+            .unwrap()
+            .port("A", "1111")
+            .port("carry", "0")
+            .port("B", "xxx1")
+            .solve()
+            .unwrap();
+        assert_eq!(res, None);
+
+        // AとXが異なる入力(B != 0)
+        let res = circuit
+            .query()
+            .unwrap()
+            .differ("A", "X")
+            .solve()
+            .unwrap()
+            .unwrap();
+        assert_ne!(value(&res, "B"), 0);
+        let res = circuit
+            .query()
+            .unwrap()
+            .equal("A", "X")
+            .port("B", "xxxx")
+            .port("carry", "0")
+            .solve()
+            .unwrap()
+            .unwrap();
+        assert_eq!(value(&res, "B"), 0);
+    }
+}
@@ -0,0 +1,305 @@
+use crate::{
+    netlist::{Bit, Netlist, Port},
+    yosys_parse::{Direction, WireId},
+    Circuit,
+};
+use std::collections::{HashMap, HashSet};
+
+/// # contents
+/// - cells: 含まれるcellのindex(昇順)
+/// - memories: 含まれるメモリのindex(昇順)
+/// - inputs: 含まれる外部入力のwireid
+/// - outputs: 含まれる出力ポートのbit("ポート名[i]")
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Cone {
+    pub cells: Vec<usize>,
+    pub memories: Vec<usize>,
+    pub inputs: Vec<WireId>,
+    pub outputs: Vec<String>,
+}
+
+/// "X"ならポートの全bit、"X[2]"ならそのbitの(ポート, bitの番号)
+fn select_bits<'a>(
+    netlist: &'a Netlist,
+    names: &[&str],
+    direction: Direction,
+) -> Result<Vec<(&'a Port, usize)>, &'static str> {
+    let mut selected = Vec::new();
+    for name in names.iter() {
+        let (port, index) = match name.strip_suffix(']').and_then(|n| n.rsplit_once('[')) {
+            Some((port, i)) => (port, Some(i.parse::<usize>().map_err(|_| "invalid bit")?)),
+            None => (*name, None),
+        };
+        let port = netlist.port(port).ok_or("selected port is not found")?;
+        if port.direction != direction {
+            return Err("port direction does not match");
+        }
+        match index {
+            Some(i) if i < port.bits.len() => selected.push((port, i)),
+            Some(_) => return Err("bit is out of range"),
+            None => selected.extend((0..port.bits.len()).map(|i| (port, i))),
+        }
+    }
+    Ok(selected)
+}
+
+/// # Sammary
+/// 出力ポート(のbit)の推移的なファンイン
+/// メモリの読み出しデータに当たったら、そのメモリのアドレス・イネーブル・書き込みデータもたどる
+pub fn fanin_cone(netlist: &Netlist, outputs: &[&str]) -> Result<Cone, &'static str> {
+    let drivers = netlist.drivers();
+    let mut mem_drivers: HashMap<WireId, usize> = HashMap::new();
+    for (m, mem) in netlist.memories.iter().enumerate() {
+        for id in mem.read_ports.iter().flat_map(|p| p.data.iter()) {
+            mem_drivers.insert(*id, m);
+        }
+    }
+    let inputs: HashSet<WireId> = netlist.input_ids().into_iter().collect();
+
+    let mut cone = Cone::default();
+    let mut stack = Vec::new();
+    for (port, i) in select_bits(netlist, outputs, Direction::Out)? {
+        cone.outputs.push(port.bit_name(i));
+        stack.extend(port.bits[i].wireid());
+    }
+    let mut seen: HashSet<WireId> = HashSet::new();
+    let (mut cells, mut memories) = (HashSet::new(), HashSet::new());
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+        if let Some(&c) = drivers.get(&id) {
+            cells.insert(c);
+            stack.extend(netlist.cells[c].inputs.iter().filter_map(Bit::wireid));
+        } else if let Some(&m) = mem_drivers.get(&id) {
+            if memories.insert(m) {
+                let mem = &netlist.memories[m];
+                let read = mem
+                    .read_ports
+                    .iter()
+                    .flat_map(|p| p.addr.iter().chain([&p.en]));
+                let write = mem
+                    .write_ports
+                    .iter()
+                    .flat_map(|p| p.en.iter().chain(p.addr.iter()).chain(p.data.iter()));
+                stack.extend(read.chain(write).filter_map(Bit::wireid));
+            }
+        } else if inputs.contains(&id) {
+            cone.inputs.push(id);
+        }
+    }
+    cone.cells = cells.into_iter().collect();
+    cone.cells.sort_unstable();
+    cone.memories = memories.into_iter().collect();
+    cone.memories.sort_unstable();
+    cone.inputs.sort_unstable();
+    Ok(cone)
+}
+
+/// # Sammary
+/// 入力ポート(のbit)の推移的なファンアウト
+/// メモリの書き込み・アドレスに当たったら、そのメモリの読み出しデータも影響を受けるとみなす
+pub fn fanout_cone(netlist: &Netlist, inputs: &[&str]) -> Result<Cone, &'static str> {
+    let mut readers: HashMap<WireId, Vec<usize>> = HashMap::new();
+    for (i, cell) in netlist.cells.iter().enumerate() {
+        for id in cell.inputs.iter().filter_map(Bit::wireid) {
+            readers.entry(id).or_default().push(i);
+        }
+    }
+    let mut mem_readers: HashMap<WireId, Vec<usize>> = HashMap::new();
+    for (m, mem) in netlist.memories.iter().enumerate() {
+        let read = mem
+            .read_ports
+            .iter()
+            .flat_map(|p| p.addr.iter().chain([&p.en]));
+        let write = mem
+            .write_ports
+            .iter()
+            .flat_map(|p| p.en.iter().chain(p.addr.iter()).chain(p.data.iter()));
+        for id in read.chain(write).filter_map(Bit::wireid) {
+            mem_readers.entry(id).or_default().push(m);
+        }
+    }
+
+    let mut cone = Cone::default();
+    let mut stack = Vec::new();
+    for (port, i) in select_bits(netlist, inputs, Direction::In)? {
+        if let Some(id) = port.bits[i].wireid() {
+            cone.inputs.push(id);
+            stack.push(id);
+        }
+    }
+    let mut seen: HashSet<WireId> = HashSet::new();
+    let (mut cells, mut memories) = (HashSet::new(), HashSet::new());
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+        for &c in readers.get(&id).into_iter().flatten() {
+            if cells.insert(c) {
+                stack.push(netlist.cells[c].output);
+            }
+        }
+        for &m in mem_readers.get(&id).into_iter().flatten() {
+            if memories.insert(m) {
+                let mem = &netlist.memories[m];
+                stack.extend(mem.read_ports.iter().flat_map(|p| p.data.iter().copied()));
+            }
+        }
+    }
+    for port in netlist.output_ports() {
+        for (i, bit) in port.bits.iter().enumerate() {
+            if bit.wireid().is_some_and(|id| seen.contains(&id)) {
+                cone.outputs.push(port.bit_name(i));
+            }
+        }
+    }
+    cone.cells = cells.into_iter().collect();
+    cone.cells.sort_unstable();
+    cone.memories = memories.into_iter().collect();
+    cone.memories.sort_unstable();
+    cone.inputs.sort_unstable();
+    Ok(cone)
+}
+
+/// # Sammary
+/// 出力ポート(のbit)を計算するのに必要な部分だけを取り出したnetlist
+/// 出力ポートは選んだbitだけに、入力ポートは使われるbitを含むものだけに絞る
+/// 入力ポートの幅は元のまま
+pub fn extract(netlist: &Netlist, outputs: &[&str]) -> Result<Netlist, &'static str> {
+    let cone = fanin_cone(netlist, outputs)?;
+    let used: HashSet<WireId> = cone.inputs.iter().copied().collect();
+    let selected = select_bits(netlist, outputs, Direction::Out)?;
+
+    let mut ports = Vec::new();
+    for port in netlist.ports.iter() {
+        match port.direction {
+            Direction::Out => {
+                let mut index: Vec<usize> = selected
+                    .iter()
+                    .filter(|(p, _)| p.name == port.name)
+                    .map(|(_, i)| *i)
+                    .collect();
+                index.sort_unstable();
+                index.dedup();
+                if !index.is_empty() {
+                    ports.push(Port {
+                        bits: index.iter().map(|&i| port.bits[i]).collect(),
+                        ..port.clone()
+                    });
+                }
+            }
+            _ => {
+                if port
+                    .bits
+                    .iter()
+                    .any(|b| b.wireid().is_some_and(|id| used.contains(&id)))
+                {
+                    ports.push(port.clone());
+                }
+            }
+        }
+    }
+
+    let cells: Vec<_> = cone
+        .cells
+        .iter()
+        .map(|&i| netlist.cells[i].clone())
+        .collect();
+    let memories: Vec<_> = cone
+        .memories
+        .iter()
+        .map(|&m| netlist.memories[m].clone())
+        .collect();
+    let mut wires: HashSet<WireId> = ports
+        .iter()
+        .flat_map(|p| p.bits.iter().filter_map(Bit::wireid))
+        .collect();
+    wires.extend(cells.iter().map(|c| c.output));
+    wires.extend(
+        cells
+            .iter()
+            .flat_map(|c| c.inputs.iter().filter_map(Bit::wireid)),
+    );
+    // ネット名は中の全bitがslice内にあるものだけ残す
+    let netnames = netlist
+        .netnames
+        .iter()
+        .filter(|(_, bits)| {
+            bits.iter()
+                .all(|b| b.wireid().is_none_or(|id| wires.contains(&id)))
+        })
+        .cloned()
+        .collect();
+
+    Ok(Netlist {
+        name: netlist.name.clone(),
+        ports,
+        cells,
+        memories,
+        netnames,
+    })
+}
+
+/// # Sammary
+/// 入力ポート(のbit)が影響する出力ポートのbitだけを計算する部分回路
+/// ファンアウトコーンの出力からextractする
+pub fn extract_fanout(netlist: &Netlist, inputs: &[&str]) -> Result<Netlist, &'static str> {
+    let cone = fanout_cone(netlist, inputs)?;
+    let outputs: Vec<&str> = cone.outputs.iter().map(|s| s.as_str()).collect();
+    if outputs.is_empty() {
+        return Err("no output is affected");
+    }
+    extract(netlist, &outputs)
+}
+
+impl<T> Circuit<T> {
+    pub fn fanin_cone(&self, outputs: &[&str]) -> Result<Cone, &'static str> {
+        fanin_cone(self.netlist(), outputs)
+    }
+    pub fn fanout_cone(&self, inputs: &[&str]) -> Result<Cone, &'static str> {
+        fanout_cone(self.netlist(), inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::equiv::{equiv, EquivOptions, Equivalence};
+
+    #[test]
+    fn ripple_slice() {
+        let netlist = Netlist::from_yosys(include_str!("../yosys/sample/ripple_res.json")).unwrap();
+        let cone = fanin_cone(&netlist, &["X[1]"]).unwrap();
+        assert_eq!(cone.inputs.len(), 4);
+        assert_eq!(cone.cells.len(), 3);
+
+        let sliced = extract(&netlist, &["X[1]"]).unwrap();
+        assert_eq!(sliced.cells.len(), 3);
+        assert_eq!(sliced.port("X").unwrap().bits.len(), 1);
+        assert!(sliced.port("carry").is_none());
+        // JSONに書き出して読み直しても回路として動く
+        let reread = Netlist::from_yosys(&sliced.to_yosys().unwrap()).unwrap();
+        let mut circuit = Circuit::<bool>::from_netlist(reread).unwrap();
+        circuit
+            .set_port("A", vec![true, true, false, false])
+            .unwrap();
+        circuit
+            .set_port("B", vec![true, false, false, false])
+            .unwrap();
+        circuit.evaluate().unwrap();
+        assert_eq!(circuit.get_port("X").unwrap(), vec![false]);
+
+        let cone = fanout_cone(&netlist, &["A[3]"]).unwrap();
+        assert_eq!(cone.outputs, vec!["X[3]", "carry"]);
+        let sliced = extract_fanout(&netlist, &["A[3]"]).unwrap();
+        assert_eq!(sliced.port("X").unwrap().bits.len(), 1);
+
+        // 全出力を取り出せば元の回路と等価
+        let whole = extract(&netlist, &["X", "carry"]).unwrap();
+        assert_eq!(
+            equiv(&netlist, &whole, &EquivOptions::default()),
+            Ok(Equivalence::Equivalent)
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{
+    netlist::{Bit, Netlist},
+    yosys_parse::{CellType, WireId},
+    Circuit,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+};
+
+/// 出力ポートのbit毎の構造
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputStats {
+    pub name: String,
+    /// 外部入力からの最大の段数
+    pub depth: usize,
+    /// 推移的に依存する外部入力(とメモリの読み出しデータ)のbit数
+    pub cone_inputs: usize,
+    /// 推移的に依存するcellの数
+    pub cone_cells: usize,
+}
+
+/// # Sammary
+/// netlistの統計
+/// # contents
+/// - cells: 種類毎のcell数
+/// - fanin/fanout: (ピン数, その数のcell・ネットの数)の分布
+/// - max_fanout: 最もファンアウトの多いネット
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub cells: Vec<(CellType, usize)>,
+    pub memories: usize,
+    pub nets: usize,
+    pub depth: usize,
+    pub fanin: Vec<(usize, usize)>,
+    pub fanout: Vec<(usize, usize)>,
+    pub max_fanout: Option<(String, usize)>,
+    pub outputs: Vec<OutputStats>,
+}
+
+pub fn stats(netlist: &Netlist) -> Result<Stats, &'static str> {
+    let order = netlist.topo_order()?;
+    let drivers = netlist.drivers();
+
+    let mut cells: HashMap<CellType, usize> = HashMap::new();
+    let mut fanin: BTreeMap<usize, usize> = BTreeMap::new();
+    // ネットのファンアウトはcellの入力ピンと出力ポートのbitの数
+    let mut fanouts: HashMap<WireId, usize> = HashMap::new();
+    for id in netlist.input_ids() {
+        fanouts.insert(id, 0);
+    }
+    for mem in netlist.memories.iter() {
+        for port in mem.read_ports.iter() {
+            for &id in port.data.iter() {
+                fanouts.insert(id, 0);
+            }
+        }
+    }
+    for cell in netlist.cells.iter() {
+        *cells.entry(cell.type_name).or_default() += 1;
+        let wires: Vec<WireId> = cell.inputs.iter().filter_map(Bit::wireid).collect();
+        *fanin.entry(wires.len()).or_default() += 1;
+        fanouts.entry(cell.output).or_insert(0);
+        for id in wires {
+            *fanouts.entry(id).or_default() += 1;
+        }
+    }
+    for id in netlist.output_ids() {
+        *fanouts.entry(id).or_default() += 1;
+    }
+    let mut fanout: BTreeMap<usize, usize> = BTreeMap::new();
+    for &n in fanouts.values() {
+        *fanout.entry(n).or_default() += 1;
+    }
+    let max_fanout = fanouts
+        .iter()
+        .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(&id, &n)| (netlist.wire_label(id), n));
+
+    let mut level: HashMap<WireId, usize> = HashMap::new();
+    for &i in order.iter() {
+        let cell = &netlist.cells[i];
+        let l = cell
+            .inputs
+            .iter()
+            .filter_map(|b| b.wireid().and_then(|id| level.get(&id)))
+            .max()
+            .copied()
+            .unwrap_or(0);
+        level.insert(cell.output, l + 1);
+    }
+
+    let mut outputs = Vec::new();
+    for port in netlist.output_ports() {
+        for (i, bit) in port.bits.iter().enumerate() {
+            let id = match bit.wireid() {
+                Some(id) => id,
+                None => continue,
+            };
+            // 出力から入力側へたどって依存するcellと入力を集める
+            let mut seen: HashSet<WireId> = HashSet::new();
+            let mut stack = vec![id];
+            let (mut cone_inputs, mut cone_cells) = (0, 0);
+            while let Some(id) = stack.pop() {
+                if !seen.insert(id) {
+                    continue;
+                }
+                match drivers.get(&id) {
+                    Some(&c) => {
+                        cone_cells += 1;
+                        stack.extend(netlist.cells[c].inputs.iter().filter_map(Bit::wireid));
+                    }
+                    None => cone_inputs += 1,
+                }
+            }
+            outputs.push(OutputStats {
+                name: port.bit_name(i),
+                depth: *level.get(&id).unwrap_or(&0),
+                cone_inputs,
+                cone_cells,
+            });
+        }
+    }
+
+    let mut cells: Vec<(CellType, usize)> = cells.into_iter().collect();
+    cells.sort_by_key(|(t, _)| t.name());
+    Ok(Stats {
+        cells,
+        memories: netlist.memories.len(),
+        nets: fanouts.len(),
+        depth: outputs.iter().map(|o| o.depth).max().unwrap_or(0),
+        fanin: fanin.into_iter().collect(),
+        fanout: fanout.into_iter().collect(),
+        max_fanout,
+        outputs,
+    })
+}
+
+impl<T> Circuit<T> {
+    pub fn stats(&self) -> Result<Stats, &'static str> {
+        stats(self.netlist())
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total: usize = self.cells.iter().map(|(_, n)| n).sum();
+        writeln!(f, "cells: {}", total)?;
+        for (t, n) in self.cells.iter() {
+            writeln!(f, "  {:<10} {}", t.name(), n)?;
+        }
+        if self.memories > 0 {
+            writeln!(f, "memories: {}", self.memories)?;
+        }
+        writeln!(f, "nets: {}", self.nets)?;
+        writeln!(f, "depth: {}", self.depth)?;
+        let hist = |h: &[(usize, usize)]| -> String {
+            h.iter()
+                .map(|(k, n)| format!("{}:{}", k, n))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        writeln!(f, "fanin: {}", hist(&self.fanin))?;
+        writeln!(f, "fanout: {}", hist(&self.fanout))?;
+        if let Some((name, n)) = &self.max_fanout {
+            writeln!(f, "max fanout: {} ({})", name, n)?;
+        }
+        writeln!(f, "outputs:")?;
+        for o in self.outputs.iter() {
+            writeln!(
+                f,
+                "  {:<12} depth {:>3}  cone inputs {:>4}  cone cells {:>6}",
+                o.name, o.depth, o.cone_inputs, o.cone_cells
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ripple_carry() {
+        let netlist = Netlist::from_yosys(include_str!("../yosys/sample/ripple_res.json")).unwrap();
+        let s = stats(&netlist).unwrap();
+        let total: usize = s.cells.iter().map(|(_, n)| n).sum();
+        assert_eq!(total, netlist.cells.len());
+        assert_eq!(s.depth, 7);
+        let x0 = s.outputs.iter().find(|o| o.name == "X[0]").unwrap();
+        assert_eq!((x0.depth, x0.cone_inputs, x0.cone_cells), (1, 2, 1));
+        let carry = s.outputs.iter().find(|o| o.name == "carry").unwrap();
+        assert_eq!(carry.cone_inputs, 8);
+        let nets: usize = s.fanout.iter().map(|(_, n)| n).sum();
+        assert_eq!(nets, s.nets);
+        assert!(s.to_string().contains("max fanout: "));
+    }
+}
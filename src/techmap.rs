@@ -0,0 +1,227 @@
+use crate::{
+    netlist::{Bit, Cell, Netlist},
+    yosys_parse::{CellType, WireId},
+};
+
+/// 書き換え先のゲートの組
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateSet {
+    Nand,
+    Nor,
+    /// ANDとNOT
+    Aig,
+}
+impl GateSet {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "nand" => Some(GateSet::Nand),
+            "nor" => Some(GateSet::Nor),
+            "aig" => Some(GateSet::Aig),
+            _ => None,
+        }
+    }
+    pub fn contains(&self, type_name: CellType) -> bool {
+        match self {
+            GateSet::Nand => type_name == CellType::Nand,
+            GateSet::Nor => type_name == CellType::Nor,
+            GateSet::Aig => matches!(type_name, CellType::And | CellType::Not),
+        }
+    }
+}
+
+/// 双対なゲート。NANDだけの回路のNANDをNORに置き換えると双対な関数になる
+fn dual(type_name: CellType) -> CellType {
+    match type_name {
+        CellType::And => CellType::Or,
+        CellType::Or => CellType::And,
+        CellType::Nand => CellType::Nor,
+        CellType::Nor => CellType::Nand,
+        CellType::Xor => CellType::Nxor,
+        CellType::Nxor => CellType::Xor,
+        t => t,
+    }
+}
+
+struct Mapper {
+    next: WireId,
+    cells: Vec<Cell>,
+}
+impl Mapper {
+    /// 途中のゲートを新しいwireに出す
+    fn gate(&mut self, name: &str, type_name: CellType, inputs: Vec<Bit>) -> Bit {
+        self.next += 1;
+        self.cells.push(Cell {
+            name: format!("{}$tm{}", name, self.cells.len()),
+            type_name,
+            inputs,
+            output: self.next,
+        });
+        Bit::Wire(self.next)
+    }
+    /// uだけでtype_nameを組み、最後のゲートの(種類, 入力)を返す
+    /// uがNORのときは双対な関数をNANDで組む手順をそのまま使う
+    fn universal(
+        &mut self,
+        u: CellType,
+        name: &str,
+        type_name: CellType,
+        ins: &[Bit],
+    ) -> (CellType, Vec<Bit>) {
+        let a = ins[0];
+        let b = ins.get(1).copied().unwrap_or(a);
+        let type_name = if u == CellType::Nand {
+            type_name
+        } else {
+            dual(type_name)
+        };
+        let mut g = |x: Bit, y: Bit| self.gate(name, u, vec![x, y]);
+        let last = match type_name {
+            CellType::Not => [a, a],
+            CellType::Nand => [a, b],
+            CellType::And => {
+                let t = g(a, b);
+                [t, t]
+            }
+            CellType::Or => [g(a, a), g(b, b)],
+            CellType::Nor => {
+                let (na, nb) = (g(a, a), g(b, b));
+                let t = g(na, nb);
+                [t, t]
+            }
+            CellType::Xor | CellType::Nxor => {
+                let t = g(a, b);
+                let (x, y) = (g(a, t), g(b, t));
+                if type_name == CellType::Xor {
+                    [x, y]
+                } else {
+                    let t = g(x, y);
+                    [t, t]
+                }
+            }
+            CellType::Mem | CellType::MemV2 => unreachable!(),
+        };
+        (u, last.to_vec())
+    }
+    /// ANDとNOTで組む
+    fn aig(&mut self, name: &str, type_name: CellType, ins: &[Bit]) -> (CellType, Vec<Bit>) {
+        use CellType::{And, Not};
+        let a = ins[0];
+        let b = ins.get(1).copied().unwrap_or(a);
+        let not = |m: &mut Self, x: Bit| m.gate(name, Not, vec![x]);
+        match type_name {
+            Not | And => (type_name, ins.to_vec()),
+            CellType::Nand => (Not, vec![self.gate(name, And, vec![a, b])]),
+            CellType::Nor => (And, vec![not(self, a), not(self, b)]),
+            CellType::Or => {
+                let (na, nb) = (not(self, a), not(self, b));
+                (Not, vec![self.gate(name, And, vec![na, nb])])
+            }
+            CellType::Xor | CellType::Nxor => {
+                // a ^ b = !(!(a & !b) & !(!a & b))
+                let (na, nb) = (not(self, a), not(self, b));
+                let x = self.gate(name, And, vec![a, nb]);
+                let y = self.gate(name, And, vec![na, b]);
+                let (nx, ny) = (not(self, x), not(self, y));
+                if type_name == CellType::Nxor {
+                    (And, vec![nx, ny])
+                } else {
+                    (Not, vec![self.gate(name, And, vec![nx, ny])])
+                }
+            }
+            CellType::Mem | CellType::MemV2 => unreachable!(),
+        }
+    }
+}
+
+/// # Sammary
+/// すべてのcellをsetのゲートだけの回路に書き換える
+/// 元のcellの名前と出力のwireは最後のゲートが引き継ぎ、途中のゲートは"名前$tm番号"になる
+/// ポート・メモリ・ネット名はそのまま
+pub fn techmap(netlist: &Netlist, set: GateSet) -> Netlist {
+    let mut mapper = Mapper {
+        next: netlist.max_wireid(),
+        cells: Vec::new(),
+    };
+    for cell in netlist.cells.iter() {
+        let (type_name, inputs) = if set.contains(cell.type_name) {
+            (cell.type_name, cell.inputs.clone())
+        } else {
+            match set {
+                GateSet::Nand => {
+                    mapper.universal(CellType::Nand, &cell.name, cell.type_name, &cell.inputs)
+                }
+                GateSet::Nor => {
+                    mapper.universal(CellType::Nor, &cell.name, cell.type_name, &cell.inputs)
+                }
+                GateSet::Aig => mapper.aig(&cell.name, cell.type_name, &cell.inputs),
+            }
+        };
+        mapper.cells.push(Cell {
+            type_name,
+            inputs,
+            ..cell.clone()
+        });
+    }
+    Netlist {
+        cells: mapper.cells,
+        ..netlist.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        equiv::{equiv, EquivOptions, Equivalence},
+        Circuit,
+    };
+
+    #[test]
+    fn universal_gates() {
+        // 全種類のゲートを含む回路
+        let mut netlist = Netlist::from_yosys(include_str!("yosys_sample.v")).unwrap();
+        let (a, b) = (
+            netlist.port("A").unwrap().bits[0],
+            netlist.port("B").unwrap().bits[0],
+        );
+        let next = netlist.max_wireid();
+        use CellType::*;
+        for (i, t) in [And, Nand, Or, Nor, Xor, Nxor, Not].into_iter().enumerate() {
+            netlist.cells.push(Cell {
+                name: format!("g{}", i),
+                type_name: t,
+                inputs: if t == Not { vec![a] } else { vec![a, b] },
+                output: next + 1 + i as WireId,
+            });
+        }
+        netlist.ports.push(crate::netlist::Port {
+            name: "Y".to_string(),
+            direction: crate::yosys_parse::Direction::Out,
+            bits: (1..=7).map(|i| Bit::Wire(next + i)).collect(),
+        });
+
+        for set in [GateSet::Nand, GateSet::Nor, GateSet::Aig] {
+            let mapped = techmap(&netlist, set);
+            assert!(mapped.cells.iter().all(|c| set.contains(c.type_name)));
+            for cell in netlist.cells.iter() {
+                assert!(mapped.cells.iter().any(|c| c.name == cell.name));
+            }
+            assert_eq!(
+                equiv(&netlist, &mapped, &EquivOptions::default()),
+                Ok(Equivalence::Equivalent)
+            );
+            // 全入力で同じ値になることをシミュレーションでも確かめる
+            let mut ca = Circuit::<bool>::from_netlist(netlist.clone()).unwrap();
+            let mut cb = Circuit::<bool>::from_netlist(mapped).unwrap();
+            for v in 0..4 {
+                for c in [&mut ca, &mut cb] {
+                    let bits = |x: usize| (0..4).map(|i| i == 0 && x & 1 == 1).collect();
+                    c.set_port("A", bits(v)).unwrap();
+                    c.set_port("B", bits(v >> 1)).unwrap();
+                    c.evaluate().unwrap();
+                }
+                assert_eq!(ca.get_port("Y").unwrap(), cb.get_port("Y").unwrap());
+            }
+        }
+    }
+}
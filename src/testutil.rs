@@ -0,0 +1,32 @@
+use crate::{
+    netlist::{Bit, Cell, Netlist},
+    yosys_parse::{CellType, WireId},
+};
+
+/// # Sammary
+/// 全加算器のサンプルに y = A[0] & !A[0] のinvとandを足したnetlistと、yのwireid
+/// yは常に0だが、invの遅延でハザードが出て、invとandの故障は冗長になる
+pub(crate) fn hazard_sample() -> (Netlist, WireId) {
+    let mut netlist = Netlist::from_yosys(include_str!("yosys_sample.v")).unwrap();
+    let a0 = netlist.port("A").unwrap().bits[0];
+    let next = netlist.max_wireid();
+    netlist.cells.push(Cell {
+        name: "inv".to_string(),
+        type_name: CellType::Not,
+        inputs: vec![a0],
+        output: next + 1,
+    });
+    netlist.cells.push(Cell {
+        name: "and".to_string(),
+        type_name: CellType::And,
+        inputs: vec![a0, Bit::Wire(next + 1)],
+        output: next + 2,
+    });
+    (netlist, next + 2)
+}
+
+/// nameのポートをbitsにつなぎ替える
+pub(crate) fn set_port_bits(netlist: &mut Netlist, name: &str, bits: Vec<Bit>) {
+    let port = netlist.ports.iter_mut().find(|p| p.name == name).unwrap();
+    port.bits = bits;
+}
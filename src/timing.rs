@@ -0,0 +1,345 @@
+use crate::{
+    netlist::{Bit, Netlist},
+    yosys_parse::{CellType, WireId},
+};
+use std::{collections::HashMap, fmt::Write};
+
+/// # Sammary
+/// セルの種類毎の遅延
+/// 表にない種類はdefaultを使う
+#[derive(Debug, Clone)]
+pub struct DelayTable {
+    delays: HashMap<CellType, f64>,
+    pub default: f64,
+    /// parse()で読み飛ばした、種類のわからないcellの名前
+    pub skipped: Vec<String>,
+}
+impl Default for DelayTable {
+    /// すべて1の単位遅延
+    fn default() -> Self {
+        DelayTable {
+            delays: HashMap::new(),
+            default: 1.0,
+            skipped: Vec::new(),
+        }
+    }
+}
+impl DelayTable {
+    pub fn set(&mut self, type_name: CellType, delay: f64) {
+        self.delays.insert(type_name, delay);
+    }
+    pub fn delay(&self, type_name: CellType) -> f64 {
+        *self.delays.get(&type_name).unwrap_or(&self.default)
+    }
+    /// # Sammary
+    /// Libertyの一部だけを読む
+    /// ```text
+    /// library (demo) {
+    ///   default_delay : 1.0 ;
+    ///   cell ("$_AND_") { delay : 1.2 ; }
+    ///   cell ("$_NOT_") { intrinsic_rise : 0.5 ; intrinsic_fall : 0.7 ; }
+    /// }
+    /// ```
+    /// cellの中のdelay, intrinsic_rise/fall, cell_rise/fallのうち最大のものを遅延とする
+    /// 種類のわからないcellは読み飛ばしてskippedに残す
+    pub fn parse(text: &str) -> Result<Self, &'static str> {
+        let tokens = tokenize(text)?;
+        let mut table = DelayTable::default();
+        let mut depth = 0usize;
+        // (セルの種類, cellの中身の深さ)。種類がNoneのcellは中身を読まない
+        let mut current: Option<(Option<CellType>, usize)> = None;
+        let mut i = 0;
+        while i < tokens.len() {
+            match tokens[i].as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    if current.is_some_and(|(_, d)| d == depth) {
+                        current = None;
+                    }
+                    depth = depth.checked_sub(1).ok_or("unbalanced braces")?;
+                }
+                "cell" if tokens.get(i + 1).map(|t| t.as_str()) == Some("(") => {
+                    let name = tokens.get(i + 2).ok_or("missing cell name")?;
+                    let type_name = CellType::from_name(name);
+                    if type_name.is_none() {
+                        table.skipped.push(name.clone());
+                    }
+                    current = Some((type_name, depth + 1));
+                    i += 3;
+                }
+                attr if tokens.get(i + 1).map(|t| t.as_str()) == Some(":") => {
+                    let value = tokens.get(i + 2).ok_or("missing value")?;
+                    i += 2;
+                    let value: f64 = match attr {
+                        "default_delay" | "delay" | "intrinsic_rise" | "intrinsic_fall"
+                        | "cell_rise" | "cell_fall" => {
+                            value.parse().map_err(|_| "invalid number")?
+                        }
+                        _ => {
+                            i += 1;
+                            continue;
+                        }
+                    };
+                    match current {
+                        Some((Some(type_name), _)) => {
+                            let d = table.delays.entry(type_name).or_insert(value);
+                            *d = d.max(value);
+                        }
+                        Some((None, _)) => {}
+                        None if attr == "default_delay" => table.default = value,
+                        None => {}
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        if depth != 0 {
+            return Err("unbalanced braces");
+        }
+        Ok(table)
+    }
+}
+
+/// 記号( ) { } : ; と、それ以外の語・数・引用符付き文字列に分ける
+fn tokenize(text: &str) -> Result<Vec<String>, &'static str> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' | ')' | '{' | '}' | ':' | ';' | ',' => tokens.push(c.to_string()),
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next().ok_or("unterminated string")? {
+                        '"' => break,
+                        c => s.push(c),
+                    }
+                }
+                tokens.push(s);
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                loop {
+                    let c = chars.next().ok_or("unterminated comment")?;
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            c => {
+                let mut s = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "(){}:;,\"".contains(c) {
+                        break;
+                    }
+                    s.push(c);
+                    chars.next();
+                }
+                tokens.push(s);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// 最悪経路の1段
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathStep {
+    /// netを出力するcell。外部入力ならNone
+    pub cell: Option<String>,
+    pub net: String,
+    pub arrival: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimingPath {
+    /// 終点の出力ポートのbit
+    pub endpoint: String,
+    pub arrival: f64,
+    pub required: f64,
+    pub slack: f64,
+    /// 始点から終点の順
+    pub steps: Vec<PathStep>,
+}
+
+/// # Sammary
+/// 静的タイミング解析の結果
+/// # contents
+/// - arrival: 外部入力(とメモリの読み出しデータ)を0とした到着時刻
+/// - required: 出力ポートの要求時刻から逆算した要求時刻。出力に届かないwireにはない
+pub struct Timing {
+    pub arrival: HashMap<WireId, f64>,
+    pub required: HashMap<WireId, f64>,
+    /// wireid -> (それを出力するcell, 到着時刻が最も遅い入力)
+    worst_fanin: HashMap<WireId, (usize, Option<WireId>)>,
+    /// (出力ポートのbit名, wireid)
+    endpoints: Vec<(String, WireId)>,
+}
+impl Timing {
+    pub fn slack(&self, id: WireId) -> Option<f64> {
+        Some(self.required.get(&id)? - self.arrival.get(&id)?)
+    }
+    /// 終点毎の最悪経路。slackの小さい順
+    pub fn paths(&self, netlist: &Netlist) -> Vec<TimingPath> {
+        let mut paths: Vec<TimingPath> = self
+            .endpoints
+            .iter()
+            .map(|(name, id)| self.path(netlist, name, *id))
+            .collect();
+        paths.sort_by(|a, b| a.slack.total_cmp(&b.slack));
+        paths
+    }
+    pub fn critical_path(&self, netlist: &Netlist) -> Option<TimingPath> {
+        self.paths(netlist).into_iter().next()
+    }
+    fn path(&self, netlist: &Netlist, endpoint: &str, id: WireId) -> TimingPath {
+        let mut steps = Vec::new();
+        let mut wire = Some(id);
+        while let Some(id) = wire {
+            let (cell, next) = match self.worst_fanin.get(&id) {
+                Some(&(cell, next)) => (Some(netlist.cells[cell].name.clone()), next),
+                None => (None, None),
+            };
+            steps.push(PathStep {
+                cell,
+                net: netlist.wire_label(id),
+                arrival: self.arrival[&id],
+            });
+            wire = next;
+        }
+        steps.reverse();
+        TimingPath {
+            endpoint: endpoint.to_string(),
+            arrival: self.arrival[&id],
+            required: self.required[&id],
+            slack: self.slack(id).unwrap(),
+            steps,
+        }
+    }
+    /// 終点毎のslackと、最悪経路のセルとネットの一覧
+    pub fn report(&self, netlist: &Netlist) -> String {
+        let mut out = String::new();
+        let paths = self.paths(netlist);
+        for path in paths.iter() {
+            writeln!(
+                out,
+                "{}: arrival {:.3}, required {:.3}, slack {:.3}",
+                path.endpoint, path.arrival, path.required, path.slack
+            )
+            .unwrap();
+        }
+        if let Some(path) = paths.first() {
+            writeln!(out, "critical path to {}:", path.endpoint).unwrap();
+            for step in path.steps.iter() {
+                writeln!(
+                    out,
+                    "  {:>8.3}  {}  {}",
+                    step.arrival,
+                    step.cell.as_deref().unwrap_or("(input)"),
+                    step.net
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+/// # Sammary
+/// 到着時刻・要求時刻・slackを求める
+/// requiredがNoneなら最も遅い到着時刻を出力ポートの要求時刻にする
+pub fn sta(
+    netlist: &Netlist,
+    delays: &DelayTable,
+    required: Option<f64>,
+) -> Result<Timing, &'static str> {
+    let order = netlist.topo_order()?;
+    let mut arrival: HashMap<WireId, f64> = HashMap::new();
+    let mut worst_fanin = HashMap::new();
+    for &i in order.iter() {
+        let cell = &netlist.cells[i];
+        let mut worst: Option<(WireId, f64)> = None;
+        for id in cell.inputs.iter().filter_map(Bit::wireid) {
+            let t = *arrival.entry(id).or_insert(0.0);
+            if worst.is_none_or(|(_, w)| t > w) {
+                worst = Some((id, t));
+            }
+        }
+        let t = worst.map_or(0.0, |(_, t)| t) + delays.delay(cell.type_name);
+        arrival.insert(cell.output, t);
+        worst_fanin.insert(cell.output, (i, worst.map(|(id, _)| id)));
+    }
+
+    let mut endpoints = Vec::new();
+    for port in netlist.output_ports() {
+        for (i, bit) in port.bits.iter().enumerate() {
+            if let Bit::Wire(id) = bit {
+                arrival.entry(*id).or_insert(0.0);
+                endpoints.push((port.bit_name(i), *id));
+            }
+        }
+    }
+    let end = required.unwrap_or_else(|| {
+        endpoints
+            .iter()
+            .map(|(_, id)| arrival[id])
+            .fold(0.0, f64::max)
+    });
+    let mut required: HashMap<WireId, f64> = endpoints.iter().map(|(_, id)| (*id, end)).collect();
+    for &i in order.iter().rev() {
+        let cell = &netlist.cells[i];
+        if let Some(&r) = required.get(&cell.output) {
+            let r = r - delays.delay(cell.type_name);
+            for id in cell.inputs.iter().filter_map(Bit::wireid) {
+                let e = required.entry(id).or_insert(r);
+                *e = e.min(r);
+            }
+        }
+    }
+
+    Ok(Timing {
+        arrival,
+        required,
+        worst_fanin,
+        endpoints,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ripple_carry() {
+        let netlist = Netlist::from_yosys(include_str!("../yosys/sample/ripple_res.json")).unwrap();
+        let timing = sta(&netlist, &DelayTable::default(), None).unwrap();
+        let path = timing.critical_path(&netlist).unwrap();
+        assert_eq!(path.slack, 0.0);
+        assert_eq!(path.steps.first().unwrap().cell, None);
+        assert_eq!(path.steps.last().unwrap().arrival, path.arrival);
+        // 単位遅延なら到着時刻は経路上のセル数に等しい
+        assert_eq!(path.arrival, (path.steps.len() - 1) as f64);
+
+        let lib = r#"
+            library (demo) {
+              /* XORだけ遅い */
+              default_delay : 0.5 ;
+              cell ("$_XOR_") { intrinsic_rise : 2.0 ; intrinsic_fall : 3.0 ; }
+              cell ("DFF_X1") { default_delay : 9.0 ; cell_rise : 9.0 ; }
+            }
+        "#;
+        let table = DelayTable::parse(lib).unwrap();
+        assert_eq!(table.delay(CellType::Xor), 3.0);
+        assert_eq!(table.delay(CellType::And), 0.5);
+        assert_eq!(table.skipped, vec!["DFF_X1".to_string()]);
+        let slow = sta(&netlist, &table, Some(path.arrival)).unwrap();
+        let worst = slow.critical_path(&netlist).unwrap();
+        assert!(worst.arrival > path.arrival * 0.5);
+        assert_eq!(worst.slack, path.arrival - worst.arrival);
+        assert!(slow.report(&netlist).contains("critical path to "));
+    }
+}
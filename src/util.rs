@@ -0,0 +1,13 @@
+/// 外部crateに頼らない簡単な乱数
+/// benchからも回路を組むのに使う
+pub struct XorShift(pub u64);
+impl XorShift {
+    // Iteratorにすると呼び出し側でunwrapが要るだけなので実装しない
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{
+    netlist::{Bit, Netlist},
+    traits::{LogicConst, LogicOps},
+    yosys_parse::WireId,
+    Circuit,
+};
+use std::{collections::HashMap, fmt};
+
+/// # Sammary
+/// 入力ベクタの集合
+/// # format
+/// ```text
+/// # コメント
+/// A B
+/// 0011 0101
+/// 1111 0001
+/// ```
+/// 最初の行がポート名、以降の行が各ポートのMSB firstの値
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VectorSet {
+    pub ports: Vec<String>,
+    /// patterns[i][j]はi番目のベクタのports[j]の値(LSB first)
+    pub patterns: Vec<Vec<Vec<bool>>>,
+}
+impl VectorSet {
+    pub fn new(ports: Vec<String>) -> Self {
+        VectorSet {
+            ports,
+            patterns: Vec::new(),
+        }
+    }
+    /// 入力ポート名順の空の集合
+    pub fn for_inputs(netlist: &Netlist) -> Self {
+        Self::new(netlist.input_ports().map(|p| p.name.clone()).collect())
+    }
+    pub fn parse(text: &str) -> Result<Self, &'static str> {
+        let mut lines = text
+            .lines()
+            .map(|l| l.split('#').next().unwrap().trim())
+            .filter(|l| !l.is_empty());
+        let header = lines.next().ok_or("missing header")?;
+        let mut set = Self::new(header.split_whitespace().map(|s| s.to_string()).collect());
+        for line in lines {
+            let values: Option<Vec<Vec<bool>>> = line
+                .split_whitespace()
+                .map(|v| {
+                    v.chars()
+                        .rev()
+                        .map(|c| match c {
+                            '0' => Some(false),
+                            '1' => Some(true),
+                            _ => None,
+                        })
+                        .collect()
+                })
+                .collect();
+            let values = values.ok_or("invalid bit")?;
+            if values.len() != set.ports.len() {
+                return Err("number of values does not match the header");
+            }
+            set.patterns.push(values);
+        }
+        Ok(set)
+    }
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+    pub fn push(&mut self, values: Vec<Vec<bool>>) {
+        self.patterns.push(values);
+    }
+    /// i番目のベクタをwireid毎の値に直す
+    pub fn assignment(
+        &self,
+        netlist: &Netlist,
+        i: usize,
+    ) -> Result<HashMap<WireId, bool>, &'static str> {
+        let mut res = HashMap::new();
+        for (name, values) in self.ports.iter().zip(self.patterns[i].iter()) {
+            let port = netlist.port(name).ok_or("selected port is not found")?;
+            if port.bits.len() != values.len() {
+                return Err("width mismatch");
+            }
+            for (bit, &v) in port.bits.iter().zip(values.iter()) {
+                if let Bit::Wire(id) = bit {
+                    res.insert(*id, v);
+                }
+            }
+        }
+        Ok(res)
+    }
+    /// 回路に順に流して出力ポートの値を集める
+    pub fn replay<T>(&self, circuit: &mut Circuit<T>) -> Result<VectorSet, &'static str>
+    where
+        T: LogicOps + LogicConst,
+    {
+        let outputs: Vec<String> = circuit
+            .netlist()
+            .output_ports()
+            .map(|p| p.name.clone())
+            .collect();
+        let mut res = VectorSet::new(outputs.clone());
+        for pattern in self.patterns.iter() {
+            for (name, values) in self.ports.iter().zip(pattern.iter()) {
+                circuit.set_port(name, values.iter().map(|&b| T::lgc_const(b)).collect())?;
+            }
+            circuit.evaluate()?;
+            let mut values = Vec::new();
+            for name in outputs.iter() {
+                let port: Option<Vec<bool>> = circuit
+                    .get_port(name)?
+                    .iter()
+                    .map(|v| v.lgc_level())
+                    .collect();
+                values.push(port.ok_or("output is not determined")?);
+            }
+            res.push(values);
+        }
+        Ok(res)
+    }
+}
+impl fmt::Display for VectorSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.ports.join(" "))?;
+        for pattern in self.patterns.iter() {
+            let values: Vec<String> = pattern
+                .iter()
+                .map(|v| v.iter().rev().map(|&b| if b { '1' } else { '0' }).collect())
+                .collect();
+            writeln!(f, "{}", values.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay() {
+        let text = "# full adder\nA B\n0011 0101\n1111 0001\n";
+        let set = VectorSet::parse(text).unwrap();
+        assert_eq!(set.to_string(), "A B\n0011 0101\n1111 0001\n");
+
+        let mut circuit = Circuit::<bool>::from_yosys(include_str!("yosys_sample.v")).unwrap();
+        let out = set.replay(&mut circuit).unwrap();
+        assert_eq!(out.to_string(), "X carry\n1000 0\n0000 1\n");
+    }
+}
@@ -1,5 +1,5 @@
-use std::collections::HashMap;
 use serde::Deserialize;
+use std::collections::HashMap;
 
 #[derive(Deserialize)]
 pub struct YosysRootElem {
@@ -15,7 +15,8 @@ impl YosysRootElem {
 
 #[derive(Deserialize)]
 pub struct ModuleElem {
-    attributes: HashMap<String, String>,
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
     pub ports: HashMap<String, PortElem>,
     pub cells: HashMap<String, CellElem>,
     pub netnames: HashMap<String, NetNameElem>,
@@ -23,13 +24,50 @@ pub struct ModuleElem {
 
 #[derive(Deserialize)]
 pub struct NetNameElem {
-    hide_name: i32,
-    pub bits: Vec<u32>,
-    attribute: serde_json::Value,
+    pub hide_name: i32,
+    pub bits: Vec<BitElem>,
+    #[serde(default)]
+    pub attributes: serde_json::Value,
 }
 
 pub type WireId = u32;
 
+/// # Sammary
+/// ポートや接続の1bit分
+/// yosysは定数を"0","1","x","z"の文字列で出力する
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(untagged)]
+pub enum BitElem {
+    Wire(WireId),
+    Const(ConstBit),
+}
+impl BitElem {
+    pub fn wireid(&self) -> Option<WireId> {
+        match self {
+            BitElem::Wire(id) => Some(*id),
+            BitElem::Const(_) => None,
+        }
+    }
+}
+
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ConstBit {
+    #[serde(rename = "0")]
+    Zero,
+    #[serde(rename = "1")]
+    One,
+    #[serde(rename = "x")]
+    X,
+    #[serde(rename = "z")]
+    Z,
+}
+impl ConstBit {
+    /// x,zは0として扱う
+    pub fn as_bool(&self) -> bool {
+        *self == ConstBit::One
+    }
+}
+
 #[derive(Deserialize, PartialEq, Clone, Copy)]
 pub enum Direction {
     #[serde(rename = "input")]
@@ -43,11 +81,11 @@ pub enum Direction {
 #[derive(Deserialize)]
 pub struct PortElem {
     pub direction: Direction,
-    pub bits: Vec<WireId>,
+    pub bits: Vec<BitElem>,
 }
 impl PortElem {}
 
-#[derive(Deserialize)]
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug, Hash)]
 pub enum CellType {
     #[serde(rename = "$_AND_")]
     And,
@@ -63,16 +101,42 @@ pub enum CellType {
     Nxor,
     #[serde(rename = "$_NOT_")]
     Not,
+    #[serde(rename = "$mem")]
+    Mem,
+    #[serde(rename = "$mem_v2")]
+    MemV2,
+}
+impl CellType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CellType::And => "$_AND_",
+            CellType::Nand => "$_NAND_",
+            CellType::Or => "$_OR_",
+            CellType::Nor => "$_NOR_",
+            CellType::Xor => "$_XOR_",
+            CellType::Nxor => "$_NXOR_",
+            CellType::Not => "$_NOT_",
+            CellType::Mem => "$mem",
+            CellType::MemV2 => "$mem_v2",
+        }
+    }
+    /// "$_AND_"などのyosysのセル名から
+    pub fn from_name(name: &str) -> Option<Self> {
+        serde_json::from_value(serde_json::Value::String(name.to_string())).ok()
+    }
 }
 #[derive(Deserialize)]
 pub struct CellElem {
     pub hide_name: i32,
     #[serde(rename = "type")]
     pub type_name: CellType,
-    parameters: serde_json::Value,
-    attributes: serde_json::Value,
+    #[serde(default)]
+    pub parameters: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub attributes: serde_json::Value,
+    #[serde(default)]
     pub port_directions: HashMap<String, Direction>,
-    pub connections: HashMap<String, Vec<WireId>>,
+    pub connections: HashMap<String, Vec<BitElem>>,
 }
 impl CellElem {
     fn output_wirename(&self) -> &String {
@@ -83,35 +147,99 @@ impl CellElem {
             .unwrap()
             .0
     }
-    pub fn output_wireids(&self) -> &Vec<WireId> {
-        self.connections.get(self.output_wirename()).unwrap()
+    pub fn output_wireids(&self) -> Vec<WireId> {
+        self.connections
+            .get(self.output_wirename())
+            .unwrap()
+            .iter()
+            .filter_map(|b| b.wireid())
+            .collect()
     }
     pub fn output_wireid(&self) -> WireId {
         *self.output_wireids().last().unwrap()
     }
+    /// ポート名順(A,B,...)に並べる
     fn input_wirenames(&self) -> Vec<&String> {
-        self.port_directions
+        let mut names: Vec<&String> = self
+            .port_directions
             .iter()
             .filter(|(_, &d)| d == Direction::In)
-            .map(|(x, y)| x)
-            .collect()
+            .map(|(x, _)| x)
+            .collect();
+        names.sort();
+        names
     }
-    pub fn input_wireids(&self) -> Vec<WireId> {
+    pub fn input_bits(&self) -> Vec<BitElem> {
         self.input_wirenames()
             .iter()
             .map(|&x| *self.connections.get(x).unwrap().last().unwrap())
             .collect()
     }
+    pub fn input_wireids(&self) -> Vec<WireId> {
+        self.input_bits()
+            .iter()
+            .filter_map(|b| b.wireid())
+            .collect()
+    }
+    pub fn connection(&self, port: &str) -> Option<&Vec<BitElem>> {
+        self.connections.get(port)
+    }
+    /// パラメータをLSB firstのbit列として取り出す
+    /// yosysは整数を数値で、それ以外をMSB firstの2進文字列で出力する
+    pub fn param_bits(&self, name: &str) -> Option<Vec<bool>> {
+        match self.parameters.get(name)? {
+            serde_json::Value::Number(n) => {
+                let n = n.as_u64()?;
+                Some((0..64).map(|i| (n >> i) & 1 == 1).collect())
+            }
+            serde_json::Value::String(s) => {
+                if !s.chars().all(|c| matches!(c, '0' | '1' | 'x' | 'z')) {
+                    return None;
+                }
+                Some(s.chars().rev().map(|c| c == '1').collect())
+            }
+            _ => None,
+        }
+    }
+    pub fn param_u64(&self, name: &str) -> Option<u64> {
+        let bits = self.param_bits(name)?;
+        Some(
+            bits.iter()
+                .take(64)
+                .enumerate()
+                .fold(0, |acc, (i, &b)| acc | ((b as u64) << i)),
+        )
+    }
+    pub fn param_str(&self, name: &str) -> Option<&str> {
+        self.parameters.get(name)?.as_str()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::YosysRootElem;
+    use super::{CellType, YosysRootElem};
 
     #[test]
     fn serialize() {
         let json = include_str!("yosys_sample.v");
-        let _res = YosysRootElem::from_json(json);
+        let res = YosysRootElem::from_json(json);
+        assert!(res.is_some());
         println!("Deserilzie success!!");
     }
+
+    #[test]
+    fn serialize_mem() {
+        let json = include_str!("../yosys/sample/ram_res.json");
+        let res = YosysRootElem::from_json(json).unwrap();
+        let module = res.modules.get("ram").unwrap();
+        let mem = module
+            .cells
+            .values()
+            .find(|c| c.type_name == CellType::Mem)
+            .unwrap();
+        assert_eq!(mem.param_u64("SIZE"), Some(4));
+        assert_eq!(mem.param_u64("WIDTH"), Some(4));
+        assert_eq!(mem.param_u64("ABITS"), Some(2));
+        assert_eq!(mem.param_str("MEMID"), Some("\\mem"));
+    }
 }
@@ -0,0 +1,14 @@
+module ram (clk,we,waddr,wdata,raddr,rdata,p);
+ input clk, we;
+ input [1:0] waddr, raddr;
+ input [3:0] wdata;
+ output [3:0] rdata;
+ output p;
+ reg [3:0] mem [0:3];
+ initial begin
+  mem[0] = 4'h1; mem[1] = 4'h2; mem[2] = 4'h4; mem[3] = 4'h8;
+ end
+ always @(posedge clk) if (we) mem[waddr] <= wdata;
+ assign rdata = mem[raddr];
+ assign p = rdata[0] ^ rdata[1];
+endmodule
@@ -0,0 +1,12 @@
+module full_adder (A,B,X,carry);
+ input [3:0] A, B;
+ output [3:0] X;
+ output carry;
+ wire [4:0] c;
+ wire [3:0] p;
+ assign c[0] = 1'b0;
+ assign p = A ^ B;
+ assign X = p ^ c[3:0];
+ assign c[4:1] = (A & B) | (p & c[3:0]);
+ assign carry = c[4];
+endmodule